@@ -1,22 +1,37 @@
 use std::{
     collections::HashMap,
+    fmt,
+    sync::Mutex,
     time::{Duration, SystemTime},
 };
 
 use derive_builder::Builder;
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use holochain_keystore::MetaLairClient;
 use holochain_types::prelude::*;
 
-use crate::types::ChainData;
+use crate::keys::{self, SeededKey, SeededKeys};
+use crate::types::{self, ChainData, CreateBuilder};
 
 pub mod agent_info;
+mod references;
+
+pub use references::References;
+
+use references::Resolver;
+
+/// Seeded chains start here unless they have a start time
+/// so their hashes don't depend on when they were made.
+const SEEDED_START_TIME: Duration = Duration::from_secs(1_640_995_200);
 
 pub struct GenerateBatch<'a, I>
 where
     I: IntoIterator<Item = ChainData>,
 {
     pub agent_data: Vec<Generate<'a, I>>,
+    /// Seeds each agent that doesn't have its own seed.
+    pub seed: Option<u64>,
+    pub references: References,
 }
 
 pub struct Generate<'a, I>
@@ -27,6 +42,49 @@ where
     pub data: I,
     pub dna_hash: DnaHash,
     pub genesis_settings: GenesisBuilder,
+    /// The same seed always makes the same author, entries and hashes.
+    /// Data that `data` makes as it is iterated comes from the seed too,
+    /// but data that is made before it is passed in must be seeded with [`types::seed`].
+    pub seed: Option<u64>,
+    /// Where to fork the chain.
+    pub forks: Vec<Fork>,
+}
+
+/// Extra branches that fork a chain.
+///
+/// Each branch starts with an element that has the same `prev_header` and
+/// `header_seq` as the data at the fork and the main chain carries on as normal.
+/// Branches are made of creates with arbitrary entries.
+///
+/// The generated chain is the main chain in order, so its head is the element
+/// for the last of the data, followed by each branch in order of where it forks.
+#[derive(Clone, Debug)]
+pub struct Fork {
+    /// The position in the data to fork at.
+    pub at: usize,
+    /// Branches beside the main chain.
+    pub branches: usize,
+    /// Elements in each branch.
+    pub length: usize,
+}
+
+impl Fork {
+    /// A single element forking the chain at this position.
+    pub fn new(at: usize) -> Self {
+        Self {
+            at,
+            branches: 1,
+            length: 1,
+        }
+    }
+}
+
+/// A fork past the end of the chain's data.
+#[derive(Clone, Debug)]
+pub struct ForkError {
+    pub at: usize,
+    /// How much data the chain has.
+    pub len: usize,
 }
 
 #[derive(Builder)]
@@ -42,68 +100,230 @@ impl<'a, I> GenerateBatch<'a, I>
 where
     I: IntoIterator<Item = ChainData>,
 {
-    pub async fn make(self) -> HashMap<AgentPubKey, Vec<Element>> {
-        let stream = self.agent_data.into_iter().map(|generate| async move {
-            let Generate {
-                keystore,
-                data,
-                dna_hash,
-                mut genesis_settings,
-            } = generate;
-            if genesis_settings.author.is_none() {
-                let author = keystore.new_sign_keypair_random().await.unwrap();
-                genesis_settings.author(author);
+    pub async fn make(self) -> Result<HashMap<AgentPubKey, Vec<Element>>, ForkError> {
+        let keys: Vec<_> = (0..self.agent_data.len())
+            .map(|i| self.seeded_key(i))
+            .collect();
+        let Self {
+            agent_data,
+            seed,
+            references,
+        } = self;
+        let rng = seed.map(fastrand::Rng::with_seed);
+        let shared = Mutex::new(Resolver::new(seed));
+        let shared = &shared;
+        let stream = agent_data.into_iter().zip(keys).map(|(mut generate, key)| {
+            if let (None, Some(rng)) = (generate.seed, &rng) {
+                generate.seed = Some(rng.u64(..));
             }
-            let genesis_data = genesis_settings.build().unwrap();
-            let author = genesis_data.author.clone();
-            let genesis_items = genesis(dna_hash.clone(), keystore, genesis_data).await;
-
-            let data = data.into_iter();
-
-            let timestamp =
-                (genesis_items[2].header().timestamp() + Duration::from_micros(1)).unwrap();
-            let prev_header = genesis_items[2].header_address().clone();
-            let mut common = HeaderBuilderCommon {
-                author: author.clone(),
-                timestamp,
-                header_seq: 3,
-                prev_header,
-            };
-            let data_stream = data.map(|ChainData { header, entry }| {
-                common.timestamp = (common.timestamp + Duration::from_micros(1)).unwrap();
-                common.header_seq += 1;
-                let header = header.build(common.clone());
-                let header = HeaderHashed::from_content_sync(header);
-                common.prev_header = header.to_hash();
-                async move {
-                    Element::new(
-                        SignedHeaderHashed::new(keystore, header).await.unwrap(),
-                        entry,
-                    )
+            async move {
+                match references {
+                    References::Arbitrary => generate.make_with(None, key).await,
+                    References::SameChain => {
+                        let own = Mutex::new(Resolver::new(generate.seed));
+                        generate.make_with(Some(&own), key).await
+                    }
+                    References::AnyChain => generate.make_with(Some(shared), key).await,
                 }
-            });
-            (
-                author,
-                futures::stream::iter(data_stream)
-                    .buffer_unordered(10)
-                    .collect()
-                    .await,
-            )
+            }
         });
+        // Chains that point at each other are built in order.
+        let concurrency = match references {
+            References::AnyChain => 1,
+            _ => 10,
+        };
         futures::stream::iter(stream)
-            .buffer_unordered(10)
-            .collect()
+            .buffer_unordered(concurrency)
+            .try_collect()
             .await
     }
+
+    /// The keys the seeded authors are made from.
+    /// Sign anything else for them, like their agent info, with these.
+    pub fn seeded_keys(&self) -> SeededKeys {
+        keys::by_agent((0..self.agent_data.len()).filter_map(|i| self.seeded_key(i)))
+    }
+
+    /// Agents with their own seed use it for their key
+    /// and the rest use the batch seed and their position.
+    fn seeded_key(&self, i: usize) -> Option<SeededKey> {
+        let generate = &self.agent_data[i];
+        match (generate.seeded_key(), self.seed) {
+            (Some(key), _) => Some(key),
+            (None, Some(seed)) if generate.genesis_settings.author.is_none() => {
+                Some(SeededKey::new(seed, i as u64))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a, I> Generate<'a, I>
+where
+    I: IntoIterator<Item = ChainData>,
+{
+    pub async fn make(self) -> Result<(AgentPubKey, Vec<Element>), ForkError> {
+        self.make_with(None, None).await
+    }
+
+    /// The key the author is made from if it is seeded.
+    /// Sign anything else for the author, like its agent info, with this.
+    pub fn seeded_key(&self) -> Option<SeededKey> {
+        match (self.seed, &self.genesis_settings.author) {
+            (Some(seed), None) => Some(SeededKey::new(seed, 0)),
+            _ => None,
+        }
+    }
+
+    /// Make the chain with references pointing at the elements in the resolver.
+    /// The author is made from the key if there is one.
+    async fn make_with(
+        self,
+        resolver: Option<&Mutex<Resolver>>,
+        key: Option<SeededKey>,
+    ) -> Result<(AgentPubKey, Vec<Element>), ForkError> {
+        let key = key.or_else(|| self.seeded_key());
+        let Generate {
+            keystore,
+            data,
+            dna_hash,
+            mut genesis_settings,
+            seed,
+            forks,
+        } = self;
+        if let Some(key) = &key {
+            genesis_settings.author(key.agent());
+        }
+        if seed.is_some() && genesis_settings.start_time.flatten().is_none() {
+            genesis_settings.start_time(Some(SystemTime::UNIX_EPOCH + SEEDED_START_TIME));
+        }
+        // Seeded data is made from this chain's own rng
+        // so other chains made on this thread can't change it.
+        let (data, mut branches) = match seed.map(fastrand::Rng::with_seed) {
+            Some(rng) => types::with_rng(&rng, || make_data(data, forks)),
+            None => make_data(data, forks),
+        }?;
+        if genesis_settings.author.is_none() {
+            let author = keystore.new_sign_keypair_random().await.unwrap();
+            genesis_settings.author(author);
+        }
+        let genesis_data = genesis_settings.build().unwrap();
+        let author = genesis_data.author.clone();
+        let key = key.as_ref();
+        let genesis_items = genesis(dna_hash.clone(), keystore, key, genesis_data).await;
+
+        let timestamp = (genesis_items[2].header().timestamp() + Duration::from_micros(1)).unwrap();
+        let prev_header = genesis_items[2].header_address().clone();
+        let mut common = HeaderBuilderCommon {
+            author: author.clone(),
+            timestamp,
+            // The first header after genesis is seq 3.
+            header_seq: 2,
+            prev_header,
+        };
+        let mut chain = Vec::with_capacity(data.len());
+        let mut forked = Vec::new();
+        for (
+            i,
+            ChainData {
+                mut header,
+                entry,
+                unresolved,
+            },
+        ) in data.into_iter().enumerate()
+        {
+            if let Some(resolver) = resolver {
+                resolver.lock().unwrap().resolve(&mut header, unresolved);
+            }
+            common.timestamp = (common.timestamp + Duration::from_micros(1)).unwrap();
+            common.header_seq += 1;
+            let fork_common = common.clone();
+            let header = header.build(common.clone());
+            let header = HeaderHashed::from_content_sync(header);
+            common.prev_header = header.to_hash();
+            if let Some(resolver) = resolver {
+                resolver.lock().unwrap().add(&header);
+            }
+            chain.push((header, entry));
+            for branch in branches.remove(&i).into_iter().flatten() {
+                let mut branch_common = fork_common.clone();
+                for (j, ChainData { header, entry, .. }) in branch.into_iter().enumerate() {
+                    if j > 0 {
+                        branch_common.timestamp =
+                            (branch_common.timestamp + Duration::from_micros(1)).unwrap();
+                        branch_common.header_seq += 1;
+                    }
+                    let header =
+                        HeaderHashed::from_content_sync(header.build(branch_common.clone()));
+                    branch_common.prev_header = header.to_hash();
+                    forked.push((header, entry));
+                }
+            }
+        }
+        chain.extend(forked);
+        let chain = chain.into_iter().map(|(header, entry)| async move {
+            Element::new(keys::sign_header(keystore, key, header).await, entry)
+        });
+        Ok((
+            author,
+            // Keep the chain in order.
+            futures::stream::iter(chain).buffered(10).collect().await,
+        ))
+    }
 }
 
+/// The branches forking off at each position in the data.
+type Branches = HashMap<usize, Vec<Vec<ChainData>>>;
+
+/// Collect the data and make the branches for the forks.
+fn make_data<I>(data: I, forks: Vec<Fork>) -> Result<(Vec<ChainData>, Branches), ForkError>
+where
+    I: IntoIterator<Item = ChainData>,
+{
+    let data: Vec<ChainData> = data.into_iter().collect();
+    let mut branches = Branches::new();
+    for fork in forks {
+        if fork.at >= data.len() {
+            return Err(ForkError {
+                at: fork.at,
+                len: data.len(),
+            });
+        }
+        let forked = branches.entry(fork.at).or_default();
+        for _ in 0..fork.branches {
+            let branch = std::iter::repeat_with(|| CreateBuilder::default().into())
+                .take(fork.length)
+                .collect();
+            forked.push(branch);
+        }
+    }
+    Ok((data, branches))
+}
+
+impl fmt::Display for ForkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Can't fork at {} in a chain with {} elements of data",
+            self.at, self.len
+        )
+    }
+}
+
+impl std::error::Error for ForkError {}
+
 // impl GenesisBuilder {
 //     fn random_agent() -> AgentPubKey {
 //         make(|u| AgentPubKey::arbitrary(u))
 //     }
 // }
 
-async fn genesis(dna_hash: DnaHash, keystore: &MetaLairClient, genesis: Genesis) -> [Element; 3] {
+async fn genesis(
+    dna_hash: DnaHash,
+    keystore: &MetaLairClient,
+    key: Option<&SeededKey>,
+    genesis: Genesis,
+) -> [Element; 3] {
     let Genesis {
         author,
         membrane_proof,
@@ -122,7 +342,7 @@ async fn genesis(dna_hash: DnaHash, keystore: &MetaLairClient, genesis: Genesis)
         hash: dna_hash,
     });
     let dna_header = HeaderHashed::from_content_sync(dna_header);
-    let dna_header = SignedHeaderHashed::new(keystore, dna_header).await.unwrap();
+    let dna_header = keys::sign_header(keystore, key, dna_header).await;
     let dna_header_address = dna_header.as_hash().clone();
     let dna_element = Element::new(dna_header, None);
 
@@ -137,9 +357,7 @@ async fn genesis(dna_hash: DnaHash, keystore: &MetaLairClient, genesis: Genesis)
         membrane_proof,
     });
     let agent_validation_header = HeaderHashed::from_content_sync(agent_validation_header);
-    let agent_validation_header = SignedHeaderHashed::new(keystore, agent_validation_header)
-        .await
-        .unwrap();
+    let agent_validation_header = keys::sign_header(keystore, key, agent_validation_header).await;
     let avh_addr = agent_validation_header.as_hash().clone();
     let avh_element = Element::new(agent_validation_header, None);
 
@@ -155,9 +373,7 @@ async fn genesis(dna_hash: DnaHash, keystore: &MetaLairClient, genesis: Genesis)
         entry_hash: author.clone().into(),
     });
     let agent_header = HeaderHashed::from_content_sync(agent_header);
-    let agent_header = SignedHeaderHashed::new(&keystore, agent_header)
-        .await
-        .unwrap();
+    let agent_header = keys::sign_header(keystore, key, agent_header).await;
     let agent_element = Element::new(agent_header, Some(Entry::Agent(author)));
     [dna_element, avh_element, agent_element]
 }
@@ -260,3 +476,129 @@ async fn genesis(dna_hash: DnaHash, keystore: &MetaLairClient, genesis: Genesis)
 // .await
 // .unwrap();
 // eprintln!("{} {:?}", i, s.elapsed());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn keystore() -> MetaLairClient {
+        holochain_keystore::test_keystore::spawn_test_keystore()
+            .await
+            .unwrap()
+    }
+
+    fn generate(
+        keystore: &MetaLairClient,
+        seed: Option<u64>,
+        forks: Vec<Fork>,
+    ) -> Generate<'_, impl IntoIterator<Item = ChainData>> {
+        Generate {
+            keystore,
+            data: std::iter::repeat_with(|| CreateBuilder::default().into()).take(5),
+            dna_hash: DnaHash::from_raw_32(vec![0; 32]),
+            genesis_settings: GenesisBuilder::default(),
+            seed,
+            forks,
+        }
+    }
+
+    fn hashes(elements: &[Element]) -> Vec<HeaderHash> {
+        elements
+            .iter()
+            .map(|e| e.header_address().clone())
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn same_seed_makes_same_hashes() {
+        let keystore = keystore().await;
+        let (author, chain) = generate(&keystore, Some(7), vec![]).make().await.unwrap();
+        let (again_author, again) = generate(&keystore, Some(7), vec![]).make().await.unwrap();
+        assert_eq!(author, again_author);
+        assert_eq!(hashes(&chain), hashes(&again));
+        assert_eq!(
+            Some(author.clone()),
+            generate(&keystore, Some(7), vec![])
+                .seeded_key()
+                .map(|k| k.agent())
+        );
+
+        let (other_author, other) = generate(&keystore, Some(8), vec![]).make().await.unwrap();
+        assert_ne!(author, other_author);
+        assert_ne!(hashes(&chain), hashes(&other));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn batch_seed_makes_each_author_from_its_key() {
+        let keystore = keystore().await;
+        let batch = || GenerateBatch {
+            agent_data: vec![
+                generate(&keystore, None, vec![]),
+                generate(&keystore, None, vec![]),
+            ],
+            seed: Some(3),
+            references: References::SameChain,
+        };
+        let keys = batch().seeded_keys();
+        assert_eq!(keys.len(), 2);
+        let data = batch().make().await.unwrap();
+        let again = batch().make().await.unwrap();
+        for key in [SeededKey::new(3, 0), SeededKey::new(3, 1)] {
+            let agent = key.agent();
+            assert_eq!(keys[&agent], key);
+            assert_eq!(hashes(&data[&agent]), hashes(&again[&agent]));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn branches_come_after_the_main_chain() {
+        let keystore = keystore().await;
+        let fork = Fork {
+            at: 1,
+            branches: 2,
+            length: 2,
+        };
+        let (_, chain) = generate(&keystore, Some(1), vec![fork])
+            .make()
+            .await
+            .unwrap();
+        assert_eq!(chain.len(), 5 + 2 * 2);
+        let (main, branches) = chain.split_at(5);
+        for (i, element) in main.iter().enumerate() {
+            assert_eq!(element.header().header_seq(), 3 + i as u32);
+            if i > 0 {
+                assert_eq!(
+                    element.header().prev_header(),
+                    Some(main[i - 1].header_address())
+                );
+            }
+        }
+        for branch in branches.chunks(2) {
+            // Each branch forks beside the second element of the main chain.
+            assert_eq!(
+                branch[0].header().prev_header(),
+                main[1].header().prev_header()
+            );
+            assert_eq!(
+                branch[0].header().header_seq(),
+                main[1].header().header_seq()
+            );
+            assert_ne!(branch[0].header_address(), main[1].header_address());
+            assert_eq!(
+                branch[1].header().prev_header(),
+                Some(branch[0].header_address())
+            );
+            assert_eq!(branch[1].header().header_seq(), 5);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn forks_past_the_data_are_an_error() {
+        let keystore = keystore().await;
+        let err = generate(&keystore, None, vec![Fork::new(5)])
+            .make()
+            .await
+            .unwrap_err();
+        assert_eq!((err.at, err.len), (5, 5));
+    }
+}
@@ -3,9 +3,12 @@ use std::sync::Arc;
 use holochain_p2p::{AgentPubKeyExt, DnaHashExt};
 use kitsune_p2p::{
     agent_store::AgentInfoSigned, dependencies::kitsune_p2p_proxy::ProxyUrl, KitsuneSignature,
+    KitsuneSpace,
 };
 use kitsune_p2p_types::{tls::TlsConfig, tx2::tx2_utils::TxUrl};
 
+use crate::keys::{self, SeededKey, SeededKeys};
+
 use super::*;
 
 pub struct GenerateAgentInfo<'a, 'b, I>
@@ -26,6 +29,9 @@ pub struct Settings {
     pub signed_at: SystemTime,
     #[builder(default = "SystemTime::now() + Duration::from_secs(60 * 60)")]
     pub expires_at: SystemTime,
+    /// Keys for agents that aren't in the keystore.
+    #[builder(default)]
+    pub seeded: SeededKeys,
 }
 
 impl<'a, 'b, I> GenerateAgentInfo<'a, 'b, I>
@@ -43,46 +49,19 @@ where
             dht_storage_arc_half_length,
             signed_at,
             expires_at,
+            seeded,
         } = settings.build().unwrap();
-        let signed_at_ms = signed_at
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-        let expires_at_ms = expires_at
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+        let seeded = &seeded;
         let stream = agent_keys.into_iter().map(|agent| {
-            let agent_kit = agent.to_kitsune();
-            let space = dna_hash.to_kitsune();
-            async move {
-                let tls = TlsConfig::new_ephemeral().await.unwrap();
-                let url: TxUrl = ProxyUrl::new("kitsune-quic://localhost:5778", tls.cert_digest)
-                    .unwrap()
-                    .as_str()
-                    .into();
-                AgentInfoSigned::sign(
-                    space,
-                    agent_kit,
-                    dht_storage_arc_half_length,
-                    vec![url],
-                    signed_at_ms,
-                    expires_at_ms,
-                    |bytes| {
-                        let bytes = bytes.to_vec();
-                        async move {
-                            Ok(
-                                holochain_keystore::AgentPubKeyExt::sign(agent, keystore, bytes)
-                                    .await
-                                    .map(|s| Arc::new(KitsuneSignature(s.0.to_vec())))
-                                    .unwrap(),
-                            )
-                        }
-                    },
-                )
-                .await
-                .unwrap()
-            }
+            sign_agent_info(
+                keystore,
+                agent,
+                seeded.get(agent),
+                dna_hash.clone(),
+                dht_storage_arc_half_length,
+                signed_at,
+                expires_at,
+            )
         });
         futures::stream::iter(stream)
             .buffer_unordered(10)
@@ -90,3 +69,113 @@ where
             .await
     }
 }
+
+/// Sign a single agent info with a new url.
+pub async fn sign_agent_info(
+    keystore: &MetaLairClient,
+    agent: &AgentPubKey,
+    key: Option<&SeededKey>,
+    dna_hash: DnaHash,
+    dht_storage_arc_half_length: u32,
+    signed_at: SystemTime,
+    expires_at: SystemTime,
+) -> AgentInfoSigned {
+    let tls = TlsConfig::new_ephemeral().await.unwrap();
+    let url: TxUrl = ProxyUrl::new("kitsune-quic://localhost:5778", tls.cert_digest)
+        .unwrap()
+        .as_str()
+        .into();
+    sign(
+        keystore,
+        agent,
+        key,
+        dna_hash.to_kitsune(),
+        dht_storage_arc_half_length,
+        vec![url],
+        signed_at,
+        expires_at,
+    )
+    .await
+}
+
+/// Sign an existing agent info again with new times.
+/// The url is kept so the mock network can still find the agent.
+pub async fn renew_agent_info(
+    keystore: &MetaLairClient,
+    info: &AgentInfoSigned,
+    key: Option<&SeededKey>,
+    signed_at: SystemTime,
+    expires_at: SystemTime,
+) -> AgentInfoSigned {
+    let agent = AgentPubKey::from_kitsune(&info.agent);
+    sign(
+        keystore,
+        &agent,
+        key,
+        info.space.clone(),
+        info.storage_arc.half_length(),
+        info.url_list.clone(),
+        signed_at,
+        expires_at,
+    )
+    .await
+}
+
+/// Sign an existing agent info again claiming a different arc.
+/// The times and url are kept.
+pub async fn resign_agent_info_with_arc(
+    keystore: &MetaLairClient,
+    info: &AgentInfoSigned,
+    key: Option<&SeededKey>,
+    dht_storage_arc_half_length: u32,
+) -> AgentInfoSigned {
+    let agent = AgentPubKey::from_kitsune(&info.agent);
+    sign(
+        keystore,
+        &agent,
+        key,
+        info.space.clone(),
+        dht_storage_arc_half_length,
+        info.url_list.clone(),
+        SystemTime::UNIX_EPOCH + Duration::from_millis(info.signed_at_ms),
+        SystemTime::UNIX_EPOCH + Duration::from_millis(info.expires_at_ms),
+    )
+    .await
+}
+
+async fn sign(
+    keystore: &MetaLairClient,
+    agent: &AgentPubKey,
+    key: Option<&SeededKey>,
+    space: Arc<KitsuneSpace>,
+    dht_storage_arc_half_length: u32,
+    url_list: Vec<TxUrl>,
+    signed_at: SystemTime,
+    expires_at: SystemTime,
+) -> AgentInfoSigned {
+    let signed_at_ms = signed_at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let expires_at_ms = expires_at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    AgentInfoSigned::sign(
+        space,
+        agent.to_kitsune(),
+        dht_storage_arc_half_length,
+        url_list,
+        signed_at_ms,
+        expires_at_ms,
+        |bytes| {
+            let bytes = bytes.to_vec();
+            async move {
+                let signature = keys::sign(keystore, agent, key, bytes).await;
+                Ok(Arc::new(KitsuneSignature(signature.0.to_vec())))
+            }
+        },
+    )
+    .await
+    .unwrap()
+}
@@ -0,0 +1,228 @@
+use crate::types::{ChainHeader, Unresolved};
+
+use super::*;
+
+/// What the references in generated headers point at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum References {
+    /// Arbitrary hashes that don't resolve to anything.
+    Arbitrary,
+    /// Earlier elements in the same chain.
+    SameChain,
+    /// Earlier elements in any chain in the batch.
+    /// Chains are built one at a time so later chains can point at earlier ones.
+    AnyChain,
+}
+
+impl Default for References {
+    fn default() -> Self {
+        References::Arbitrary
+    }
+}
+
+/// The elements generated so far that references can point at.
+pub(super) struct Resolver {
+    rng: fastrand::Rng,
+    /// Entries that can be linked from or to.
+    entries: Vec<EntryHash>,
+    /// Elements with an app entry that can be updated or deleted.
+    originals: Vec<(HeaderHash, EntryHash, EntryType)>,
+    /// Links that haven't been deleted yet.
+    links: Vec<(HeaderHash, EntryHash)>,
+}
+
+impl Resolver {
+    pub(super) fn new(seed: Option<u64>) -> Self {
+        Self {
+            rng: seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            entries: Vec::new(),
+            originals: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+
+    /// Point the unresolved references at earlier elements.
+    /// References with nothing to point at are left as they are.
+    pub(super) fn resolve(&mut self, header: &mut ChainHeader, unresolved: Unresolved) {
+        match header {
+            ChainHeader::CreateLink(link) => {
+                if unresolved.base {
+                    if let Some(base) = pick(&self.rng, &self.entries) {
+                        link.base_address = base.clone();
+                    }
+                }
+                if unresolved.target {
+                    if let Some(target) = pick(&self.rng, &self.entries) {
+                        link.target_address = target.clone();
+                    }
+                }
+            }
+            ChainHeader::DeleteLink(delete) if unresolved.original => {
+                if let Some((link, base)) = take(&self.rng, &mut self.links) {
+                    delete.link_add_address = link;
+                    delete.base_address = base;
+                }
+            }
+            ChainHeader::Update(update) if unresolved.original => {
+                if let Some((header, entry, entry_type)) = pick(&self.rng, &self.originals) {
+                    update.original_header_address = header.clone();
+                    update.original_entry_address = entry.clone();
+                    // Updates must keep the entry type.
+                    update.entry_type = entry_type.clone();
+                }
+            }
+            ChainHeader::Delete(delete) if unresolved.original => {
+                if let Some((header, entry, _)) = take(&self.rng, &mut self.originals) {
+                    // Nothing new should link from or to the deleted element's entry.
+                    if let Some(i) = self.entries.iter().position(|e| *e == entry) {
+                        self.entries.swap_remove(i);
+                    }
+                    delete.deletes_address = header;
+                    delete.deletes_entry_address = entry;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Let later references point at this element.
+    pub(super) fn add(&mut self, hashed: &HeaderHashed) {
+        let hash = hashed.as_hash().clone();
+        match hashed.as_content() {
+            Header::Create(header::Create {
+                entry_type,
+                entry_hash,
+                ..
+            })
+            | Header::Update(header::Update {
+                entry_type,
+                entry_hash,
+                ..
+            }) => {
+                self.entries.push(entry_hash.clone());
+                if let EntryType::App(_) = entry_type {
+                    self.originals
+                        .push((hash, entry_hash.clone(), entry_type.clone()));
+                }
+            }
+            Header::CreateLink(link) => self.links.push((hash, link.base_address.clone())),
+            _ => (),
+        }
+    }
+}
+
+fn pick<'a, T>(rng: &fastrand::Rng, items: &'a [T]) -> Option<&'a T> {
+    (!items.is_empty()).then(|| &items[rng.usize(..items.len())])
+}
+
+/// Pick an item so nothing else can point at it.
+fn take<T>(rng: &fastrand::Rng, items: &mut Vec<T>) -> Option<T> {
+    (!items.is_empty()).then(|| items.swap_remove(rng.usize(..items.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{
+        make, ChainData, CreateLinkBuilder, DeleteBuilder, DeleteLinkBuilder, UpdateBuilder,
+    };
+
+    use super::*;
+
+    /// Add a create of this app entry to the resolver.
+    fn create(resolver: &mut Resolver, entry: u8) -> (HeaderHash, EntryHash, EntryType) {
+        let mut create: header::Create = make(|u| u.arbitrary());
+        create.entry_type = EntryType::App(make(|u| u.arbitrary()));
+        create.entry_hash = EntryHash::from_raw_32(vec![entry; 32]);
+        let hashed = HeaderHashed::from_content_sync(Header::Create(create.clone()));
+        resolver.add(&hashed);
+        (
+            hashed.as_hash().clone(),
+            create.entry_hash,
+            create.entry_type,
+        )
+    }
+
+    fn resolve(resolver: &mut Resolver, data: impl Into<ChainData>) -> ChainHeader {
+        let mut data = data.into();
+        resolver.resolve(&mut data.header, data.unresolved);
+        data.header
+    }
+
+    #[test]
+    fn links_point_at_earlier_entries() {
+        let mut resolver = Resolver::new(Some(1));
+        let (_, entry, _) = create(&mut resolver, 1);
+        match resolve(&mut resolver, CreateLinkBuilder::default()) {
+            ChainHeader::CreateLink(link) => {
+                assert_eq!(link.base_address, entry);
+                assert_eq!(link.target_address, entry);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn updates_keep_the_original_entry_type() {
+        let mut resolver = Resolver::new(Some(1));
+        let (header, entry, entry_type) = create(&mut resolver, 1);
+        match resolve(&mut resolver, UpdateBuilder::default()) {
+            ChainHeader::Update(update) => {
+                assert_eq!(update.original_header_address, header);
+                assert_eq!(update.original_entry_address, entry);
+                assert_eq!(update.entry_type, entry_type);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn deleted_elements_are_not_resolved_again() {
+        let mut resolver = Resolver::new(Some(1));
+        let (header, entry, _) = create(&mut resolver, 1);
+        match resolve(&mut resolver, DeleteBuilder::default()) {
+            ChainHeader::Delete(delete) => {
+                assert_eq!(delete.deletes_address, header);
+                assert_eq!(delete.deletes_entry_address, entry);
+            }
+            _ => unreachable!(),
+        }
+        match resolve(&mut resolver, DeleteBuilder::default()) {
+            ChainHeader::Delete(delete) => assert_ne!(delete.deletes_address, header),
+            _ => unreachable!(),
+        }
+        match resolve(&mut resolver, CreateLinkBuilder::default()) {
+            ChainHeader::CreateLink(link) => {
+                assert_ne!(link.base_address, entry);
+                assert_ne!(link.target_address, entry);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn links_are_only_deleted_once() {
+        let mut resolver = Resolver::new(Some(1));
+        create(&mut resolver, 1);
+        let link = match resolve(&mut resolver, CreateLinkBuilder::default()) {
+            ChainHeader::CreateLink(link) => link,
+            _ => unreachable!(),
+        };
+        let mut header: header::CreateLink = make(|u| u.arbitrary());
+        header.base_address = link.base_address.clone();
+        let hashed = HeaderHashed::from_content_sync(Header::CreateLink(header));
+        resolver.add(&hashed);
+        match resolve(&mut resolver, DeleteLinkBuilder::default()) {
+            ChainHeader::DeleteLink(delete) => {
+                assert_eq!(delete.link_add_address, *hashed.as_hash());
+                assert_eq!(delete.base_address, link.base_address);
+            }
+            _ => unreachable!(),
+        }
+        match resolve(&mut resolver, DeleteLinkBuilder::default()) {
+            ChainHeader::DeleteLink(delete) => {
+                assert_ne!(delete.link_add_address, *hashed.as_hash())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use holochain_keystore::MetaLairClient;
+use holochain_p2p::mock_network::{AddressedHolochainP2pMockMsg, HolochainP2pMockMsg};
+use holochain_p2p::AgentPubKeyExt;
+use holochain_types::prelude::{AgentPubKey, DnaHash};
+use kitsune_p2p::{agent_store::AgentInfoSigned, KitsuneP2pConfig};
+
+use crate::agent_info::{GenerateAgentInfo, SettingsBuilder};
+
+use super::*;
+
+/// Many real conductors sharing one simulated network.
+///
+/// Each conductor gets its own [`MockNetwork`] and one real agent.
+/// Messages between real conductors are forwarded by the hub after
+/// passing through the sending conductor's network so schedules,
+/// partitions and links still apply.
+/// The sending network owns the conditions so they are applied once,
+/// and the receiving network hands the message straight to its conductor.
+/// Requests are forwarded the same way and the receiving conductor's
+/// response goes back through the sending network.
+/// Messages to simulated agents are handed to the caller.
+pub struct MockHub {
+    networks: Vec<MockNetwork>,
+    configs: Vec<KitsuneP2pConfig>,
+    agents: Vec<AgentPubKey>,
+    peer_data: Vec<Vec<AgentInfoSigned>>,
+    conductor_of: HashMap<AgentPubKey, usize>,
+    /// Which networks are still open.
+    open: Vec<bool>,
+}
+
+/// A message seen by the hub.
+pub enum HubMsg {
+    /// A message from a real conductor to a simulated agent.
+    Simulated {
+        conductor: usize,
+        msg: AddressedHolochainP2pMockMsg,
+        respond: Option<MockRespond>,
+    },
+    /// A message that was forwarded between real conductors.
+    Forwarded {
+        from: usize,
+        to: usize,
+        msg: AddressedHolochainP2pMockMsg,
+    },
+}
+
+impl MockHub {
+    /// Create a hub for this many conductors.
+    /// Every network is created with the same settings.
+    ///
+    /// The real agents are made in this keystore
+    /// so every conductor must be started with it.
+    pub async fn new(
+        keystore: &MetaLairClient,
+        dna_hash: DnaHash,
+        num_conductors: usize,
+        simulated: Vec<AgentInfoSigned>,
+        settings: NetworkSettingsBuilder,
+    ) -> Self {
+        let mut agents = Vec::with_capacity(num_conductors);
+        for _ in 0..num_conductors {
+            agents.push(keystore.new_sign_keypair_random().await.unwrap());
+        }
+        let real = GenerateAgentInfo {
+            keystore,
+            agent_keys: agents.iter(),
+            dna_hash,
+            settings: SettingsBuilder::default(),
+        }
+        .make()
+        .await;
+        let real: HashMap<_, _> = real
+            .into_iter()
+            .map(|info| (AgentPubKey::from_kitsune(&info.agent), info))
+            .collect();
+
+        let mut networks = Vec::with_capacity(num_conductors);
+        let mut configs = Vec::with_capacity(num_conductors);
+        let mut peer_data = Vec::with_capacity(num_conductors);
+        for agent in &agents {
+            let data: Vec<_> = simulated
+                .iter()
+                .cloned()
+                .chain(
+                    real.iter()
+                        .filter(|(other, _)| *other != agent)
+                        .map(|(_, info)| info.clone()),
+                )
+                .collect();
+            let (mut network, config) = setup_with(data.clone(), settings.clone());
+            network.add_local_agent(agent.clone());
+            networks.push(network);
+            configs.push(config);
+            peer_data.push(data);
+        }
+        let conductor_of = agents
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, agent)| (agent, i))
+            .collect();
+        Self {
+            open: vec![true; networks.len()],
+            networks,
+            configs,
+            agents,
+            peer_data,
+            conductor_of,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// The network config for this conductor.
+    pub fn config(&self, conductor: usize) -> KitsuneP2pConfig {
+        self.configs[conductor].clone()
+    }
+
+    /// The real agent this conductor must install its app with.
+    pub fn agent(&self, conductor: usize) -> &AgentPubKey {
+        &self.agents[conductor]
+    }
+
+    /// The agent info this conductor should be given at startup.
+    pub fn peer_data(&self, conductor: usize) -> &[AgentInfoSigned] {
+        &self.peer_data[conductor]
+    }
+
+    pub fn network(&self, conductor: usize) -> &MockNetwork {
+        &self.networks[conductor]
+    }
+
+    pub fn network_mut(&mut self, conductor: usize) -> &mut MockNetwork {
+        &mut self.networks[conductor]
+    }
+
+    /// Get the next message from any conductor.
+    /// Messages between conductors are forwarded before they are returned.
+    ///
+    /// This is cancel safe.
+    pub async fn next(&mut self) -> Option<HubMsg> {
+        loop {
+            if !self.open.iter().any(|open| *open) {
+                return None;
+            }
+            let open = &self.open;
+            let ((from, next), _, _) = futures::future::select_all(
+                self.networks
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(i, _)| open[*i])
+                    .map(|(i, network)| Box::pin(async move { (i, network.next().await) })),
+            )
+            .await;
+            let (msg, respond) = match next {
+                Some(next) => next,
+                None => {
+                    self.open[from] = false;
+                    continue;
+                }
+            };
+            let to = match self.conductor_of.get(&msg.agent) {
+                Some(to) => *to,
+                None => {
+                    return Some(HubMsg::Simulated {
+                        conductor: from,
+                        msg,
+                        respond,
+                    })
+                }
+            };
+            let from_agent = match &msg.msg {
+                HolochainP2pMockMsg::Wire { from_agent, .. } => from_agent.clone(),
+                _ => self.agents[from].clone(),
+            };
+            let forward = AddressedHolochainP2pMockMsg {
+                agent: from_agent,
+                msg: msg.msg.clone(),
+            };
+            match respond {
+                Some(respond) => self.networks[to].forward_request(forward, respond),
+                None => self.networks[to].forward(forward),
+            }
+            return Some(HubMsg::Forwarded { from, to, msg });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use holochain::conductor::config::ConductorConfig;
+    use holochain::conductor::ConductorBuilder;
+    use holochain::sweettest::*;
+    use holochain_types::prelude::*;
+
+    use super::*;
+
+    /// Calls from other agents to `ping` count up the caller's slot.
+    fn zome(pings: Arc<[AtomicUsize; 2]>) -> InlineZome {
+        InlineZome::new_unique(vec![])
+            .callback("grant", |api, ()| {
+                let mut functions = GrantedFunctions::new();
+                functions.insert(("zome1".into(), "ping".into()));
+                let grant = ZomeCallCapGrant {
+                    tag: "ping".into(),
+                    access: CapAccess::Unrestricted,
+                    functions,
+                };
+                api.create(CreateInput::new(
+                    EntryDefId::CapGrant,
+                    Entry::CapGrant(grant),
+                    ChainTopOrdering::default(),
+                ))?;
+                Ok(())
+            })
+            .callback("ping", move |_api, from: usize| {
+                pings[from].fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .callback("call_ping", |api, (to, from): (AgentPubKey, usize)| {
+                let call = CallRemote::new(
+                    to,
+                    "zome1".into(),
+                    "ping".into(),
+                    None,
+                    ExternIO::encode(from)?,
+                );
+                let response = api.call_remote(vec![call])?;
+                assert!(matches!(response[0], ZomeCallResponse::Ok(_)));
+                Ok(())
+            })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn forwarded_requests_arrive_once() {
+        let pings = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+        let (dna_file, _) = SweetDnaFile::from_inline_zome("".into(), "zome1", zome(pings.clone()))
+            .await
+            .unwrap();
+        let keystore = holochain_keystore::test_keystore::spawn_test_keystore()
+            .await
+            .unwrap();
+        let mut hub = MockHub::new(
+            &keystore,
+            dna_file.dna_hash().clone(),
+            2,
+            Vec::new(),
+            NetworkSettingsBuilder::default(),
+        )
+        .await;
+
+        let mut conductors = Vec::new();
+        let mut zomes = Vec::new();
+        for i in 0..hub.len() {
+            let config = ConductorConfig {
+                network: Some(hub.config(i)),
+                ..Default::default()
+            };
+            let builder = ConductorBuilder::new()
+                .config(config)
+                .with_keystore(keystore.clone());
+            let mut conductor = SweetConductor::from_builder(builder).await;
+            conductor
+                .add_agent_infos(hub.peer_data(i).to_vec())
+                .await
+                .unwrap();
+            let app = conductor
+                .setup_app_for_agent("app", hub.agent(i).clone(), &[dna_file.clone()])
+                .await
+                .unwrap();
+            let (cell,) = app.into_tuple();
+            zomes.push(cell.zome("zome1"));
+            conductors.push(conductor);
+        }
+        let agents: Vec<_> = (0..hub.len()).map(|i| hub.agent(i).clone()).collect();
+
+        // Keep both networks busy while the requests are in flight.
+        let hub_task = tokio::spawn(async move { while hub.next().await.is_some() {} });
+
+        for (conductor, zome) in conductors.iter().zip(&zomes) {
+            let _: () = conductor.call(zome, "grant", ()).await;
+        }
+        // Conductor 1 sends a steady stream of requests
+        // while conductor 0 sends just one.
+        let busy = async {
+            for _ in 0..20 {
+                let _: () = conductors[1]
+                    .call(&zomes[1], "call_ping", (agents[0].clone(), 1usize))
+                    .await;
+            }
+        };
+        let single = async {
+            let _: () = conductors[0]
+                .call(&zomes[0], "call_ping", (agents[1].clone(), 0usize))
+                .await;
+        };
+        futures::join!(busy, single);
+
+        // Anything sent again would arrive after the response.
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(pings[0].load(Ordering::SeqCst), 1);
+        assert_eq!(pings[1].load(Ordering::SeqCst), 20);
+        hub_task.abort();
+    }
+}
@@ -1,11 +1,20 @@
 use holochain::core::Timestamp;
 use holochain_state::prelude::*;
 use holochain_types::{
-    dht_op::{produce_op_lights_from_elements, DhtOpType, OpOrder, UniqueForm},
-    prelude::{Element, EntryVisibility, ValidationStatus},
+    dht_op::{produce_op_lights_from_elements, DhtOp, DhtOpType, OpOrder, UniqueForm},
+    prelude::{
+        AnyDhtHash, DhtOpHash, Element, EntryVisibility, Header, HeaderHash, HeaderHashed,
+        SignedHeaderHashed, ValidationStatus,
+    },
 };
 use rusqlite::{params, CachedStatement, ToSql, Transaction};
 
+const INSERT_OP: &str = "INSERT INTO DhtOp (hash, type, basis_hash, header_hash,
+                    storage_center_loc, authored_timestamp, op_order,
+                    validation_status, when_integrated, require_receipt,
+                    num_validation_attempts, last_validation_attempt, dependency) 
+                    VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
 // pub fn bulk_insert_element_as_authority<'a>(
 //     txn: &mut Transaction,
 //     elements: impl Iterator<Item = &'a Element>,
@@ -32,18 +41,50 @@ pub fn insert_element_as_authority(txn: &mut Transaction, element: &Element) {
         insert_entry(txn, element.header().entry_hash().as_ref().unwrap(), entry).unwrap();
     }
     insert_header(txn, element.signed_header()).unwrap();
-    let mut stmt = txn
-        .prepare_cached(
-            "INSERT INTO DhtOp (hash, type, basis_hash, header_hash,
-                    storage_center_loc, authored_timestamp, op_order,
-                    validation_status, when_integrated, require_receipt,
-                    num_validation_attempts, last_validation_attempt, dependency) 
-                    VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-        )
-        .unwrap();
+    let mut stmt = txn.prepare_cached(INSERT_OP).unwrap();
     commit_ops(&mut stmt, element);
 }
 
+/// Store an op that was sent to an authority along with its header and entry.
+/// Anything already stored is left as it is.
+pub fn insert_op_as_authority(txn: &mut Transaction, op: &DhtOp) {
+    let op_hash = DhtOpHash::with_data_sync(op);
+    if exists(txn, "DhtOp", &op_hash) {
+        return;
+    }
+    let header = op.header();
+    if let (Some(entry), Some(entry_hash)) = (op.entry(), header.entry_hash()) {
+        if !exists(txn, "Entry", entry_hash) {
+            insert_entry(txn, entry_hash, entry).unwrap();
+        }
+    }
+    let header = SignedHeaderHashed::with_presigned(
+        HeaderHashed::from_content_sync(header),
+        op.signature().clone(),
+    );
+    if !exists(txn, "Header", header.header_address()) {
+        insert_header(txn, &header).unwrap();
+    }
+    let mut stmt = txn.prepare_cached(INSERT_OP).unwrap();
+    insert_op(
+        &mut stmt,
+        op_hash,
+        op.get_type(),
+        op.dht_basis(),
+        header.header_address(),
+        header.header(),
+    );
+}
+
+fn exists(txn: &Transaction, table: &str, hash: &dyn ToSql) -> bool {
+    txn.query_row(
+        &format!("SELECT EXISTS(SELECT 1 FROM {} WHERE hash = ?)", table),
+        [hash],
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
 fn commit_ops(stmt: &mut CachedStatement<'_>, element: &Element) {
     for ops in produce_op_lights_from_elements(vec![element]) {
         for op in ops {
@@ -58,39 +99,114 @@ fn commit_ops(stmt: &mut CachedStatement<'_>, element: &Element) {
             let op_hash = UniqueForm::op_hash(op_type, element.header().clone())
                 .unwrap()
                 .1;
-            let basis_hash = op.dht_basis();
-            let header_hash = element.header_address();
-            let storage_center_loc = basis_hash.get_loc();
-            let authored_timestamp = element.header().timestamp();
-            let op_order = OpOrder::new(op_type, authored_timestamp);
-            let validation_status = ValidationStatus::Valid;
-            let when_integrated = Timestamp::now();
-            let require_receipt = false;
-            let num_validation_attempts: i32 = 1;
-            let last_validation_attempt = Timestamp::now();
-            let dep = get_dependency(op_type, element.header());
-            let dependency = match &dep {
-                Dependency::Header(h) => Some(h.to_sql().unwrap()),
-                Dependency::Entry(e) => Some(e.to_sql().unwrap()),
-                Dependency::Null => None,
-            };
-
-            stmt.execute(params![
+            insert_op(
+                stmt,
                 op_hash,
                 op_type,
-                basis_hash,
-                header_hash,
-                storage_center_loc,
-                authored_timestamp,
-                op_order,
-                validation_status,
-                when_integrated,
-                require_receipt,
-                num_validation_attempts,
-                last_validation_attempt,
-                dependency
-            ])
-            .unwrap();
+                op.dht_basis().clone(),
+                element.header_address(),
+                element.header(),
+            );
         }
     }
 }
+
+fn insert_op(
+    stmt: &mut CachedStatement<'_>,
+    op_hash: DhtOpHash,
+    op_type: DhtOpType,
+    basis_hash: AnyDhtHash,
+    header_hash: &HeaderHash,
+    header: &Header,
+) {
+    let storage_center_loc = basis_hash.get_loc();
+    let authored_timestamp = header.timestamp();
+    let op_order = OpOrder::new(op_type, authored_timestamp);
+    let validation_status = ValidationStatus::Valid;
+    let when_integrated = Timestamp::now();
+    let require_receipt = false;
+    let num_validation_attempts: i32 = 1;
+    let last_validation_attempt = Timestamp::now();
+    let dep = get_dependency(op_type, header);
+    let dependency = match &dep {
+        Dependency::Header(h) => Some(h.to_sql().unwrap()),
+        Dependency::Entry(e) => Some(e.to_sql().unwrap()),
+        Dependency::Null => None,
+    };
+
+    stmt.execute(params![
+        op_hash,
+        op_type,
+        basis_hash,
+        header_hash,
+        storage_center_loc,
+        authored_timestamp,
+        op_order,
+        validation_status,
+        when_integrated,
+        require_receipt,
+        num_validation_attempts,
+        last_validation_attempt,
+        dependency
+    ])
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use holochain_state::test_utils::test_dht_db;
+    use holochain_types::prelude::*;
+
+    use super::*;
+    use crate::types::make;
+
+    fn count(txn: &Transaction, table: &str) -> i64 {
+        txn.query_row(
+            &format!("SELECT COUNT(*) FROM {}", table),
+            params![],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ops_share_their_header_and_entry() {
+        let entry = Entry::App(make(|u| u.arbitrary()));
+        let mut create: Create = make(|u| u.arbitrary());
+        create.entry_type = EntryType::App(make(|u| u.arbitrary()));
+        create.entry_hash = EntryHash::with_data_sync(&entry);
+        let signature = Signature([1; 64]);
+        let store_entry = DhtOp::StoreEntry(
+            signature.clone(),
+            NewEntryHeader::Create(create.clone()),
+            Box::new(entry.clone()),
+        );
+        let store_element =
+            DhtOp::StoreElement(signature, Header::Create(create), Some(Box::new(entry)));
+
+        let db = test_dht_db();
+        db.to_db()
+            .conn()
+            .unwrap()
+            .with_commit_sync(|txn| {
+                insert_op_as_authority(txn, &store_entry);
+                insert_op_as_authority(txn, &store_element);
+                // Storing an op again changes nothing.
+                insert_op_as_authority(txn, &store_entry);
+                assert_eq!(count(txn, "DhtOp"), 2);
+                assert_eq!(count(txn, "Header"), 1);
+                assert_eq!(count(txn, "Entry"), 1);
+                let hash = DhtOpHash::with_data_sync(&store_entry);
+                let stored: i64 = txn
+                    .query_row(
+                        "SELECT COUNT(*) FROM DhtOp WHERE hash = ?",
+                        params![hash],
+                        |row| row.get(0),
+                    )
+                    .unwrap();
+                assert_eq!(stored, 1);
+                DatabaseResult::Ok(())
+            })
+            .unwrap();
+    }
+}
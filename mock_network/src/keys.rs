@@ -0,0 +1,96 @@
+//! Agent keys that can be made again from a seed.
+//!
+//! The keystore only makes random keys so seeded agents are signed for here.
+//! Nothing is kept between signatures: a [`SeededKey`] is just the seed and
+//! index, and the secret key is derived from them each time it is needed.
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use holochain_keystore::{AgentPubKeyExt, MetaLairClient};
+use holochain_types::prelude::*;
+use serde::Serialize;
+
+/// Seeded keys by the agent they make.
+pub type SeededKeys = HashMap<AgentPubKey, SeededKey>;
+
+/// An agent key made from a seed instead of by the keystore.
+/// The same seed and index always make the same key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SeededKey {
+    pub seed: u64,
+    pub index: u64,
+}
+
+impl SeededKey {
+    pub fn new(seed: u64, index: u64) -> Self {
+        Self { seed, index }
+    }
+
+    pub fn agent(&self) -> AgentPubKey {
+        AgentPubKey::from_raw_32(self.keypair().public.to_bytes().to_vec())
+    }
+
+    /// Sign the serialized data the same way the keystore would.
+    pub fn sign<S>(&self, data: S) -> Signature
+    where
+        S: Serialize + Debug,
+    {
+        let bytes = holochain_serialized_bytes::encode(&data).unwrap();
+        Signature(self.keypair().sign(&bytes).to_bytes().into())
+    }
+
+    fn keypair(&self) -> Keypair {
+        let rng = fastrand::Rng::with_seed(self.seed);
+        // Reseed with the seed's first number mixed with the index
+        // so each index gets a different key.
+        rng.seed(rng.u64(..) ^ self.index);
+        let mut bytes = [0; 32];
+        bytes.iter_mut().for_each(|b| *b = rng.u8(..));
+        let secret = SecretKey::from_bytes(&bytes).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+}
+
+/// Sign the serialized data as this agent,
+/// with its seeded key if it has one or else with the keystore.
+pub async fn sign<S>(
+    keystore: &MetaLairClient,
+    agent: &AgentPubKey,
+    key: Option<&SeededKey>,
+    data: S,
+) -> Signature
+where
+    S: Serialize + Debug,
+{
+    match key {
+        Some(key) => key.sign(data),
+        None => agent
+            .sign(keystore, data)
+            .await
+            .expect("Failed to sign with the keystore"),
+    }
+}
+
+/// Sign a header as its author.
+pub async fn sign_header(
+    keystore: &MetaLairClient,
+    key: Option<&SeededKey>,
+    header: HeaderHashed,
+) -> SignedHeaderHashed {
+    let signature = sign(
+        keystore,
+        header.as_content().author(),
+        key,
+        header.as_content(),
+    )
+    .await;
+    SignedHeaderHashed::with_presigned(header, signature)
+}
+
+/// Key these by the agent they make.
+/// Each agent is derived once here so lookups don't have to.
+pub fn by_agent(keys: impl IntoIterator<Item = SeededKey>) -> SeededKeys {
+    keys.into_iter().map(|key| (key.agent(), key)).collect()
+}
@@ -1,10 +1,23 @@
 mod generate_test_data;
+mod hub;
 mod network;
 mod setup;
 mod insert_data;
+pub mod keys;
 pub mod types;
+#[cfg(test)]
+mod test_utils;
 
-pub use network::MockNetwork;
-pub use setup::setup;
+pub use hub::{HubMsg, MockHub};
+pub use network::{
+    chrome_trace, clean, read_recording, write_chrome_trace, Action, AuthorityResponder,
+    BufferStats, Byzantine, ByzantineSettings, ByzantineSettingsBuilder, Churn, ChurnSettings,
+    ChurnSettingsBuilder, Clock, Count, Direction, ExpectError, Flow, GossipResponder, Histogram,
+    Intercept, Interceptor, LinkProfile, MockNetwork, MockRespond, MockSender, MsgFilter, MsgKind,
+    NoLocalAgents, Partition, Phase, ReceiptResponder, ReceiptSettings, ReceiptSettingsBuilder,
+    Record, RecordedMsg, Replay, Reply, Responder, Runtime, RuntimeReport, RuntimeSettings,
+    RuntimeSettingsBuilder, Schedule, SimulatedStore, Stats, VirtualClock, RECORDING_VERSION,
+};
+pub use setup::{default_tuning, setup, setup_with, NetworkSettings, NetworkSettingsBuilder};
 pub use generate_test_data::*;
-pub use insert_data::insert_element_as_authority;
+pub use insert_data::{insert_element_as_authority, insert_op_as_authority};
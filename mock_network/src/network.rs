@@ -1,20 +1,964 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::Stream;
 use holochain_p2p::mock_network::*;
+use holochain_types::prelude::AgentPubKey;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::NetworkSettings;
+
+use self::intercept::Chain;
+use self::link::{Pipe, Pipes};
+use self::queue::DelayQueue;
+use self::record::Recorder;
+use self::schedule::{chance, random_delay};
+use self::size::msg_size;
+use self::stats::SharedStats;
+
+mod authority;
+mod byzantine;
+mod churn;
+mod clock;
+mod filter;
+mod gossip;
+mod intercept;
+mod link;
+mod partition;
+mod queue;
+mod receipts;
+mod record;
+mod responder;
+mod runtime;
+mod schedule;
+mod size;
+mod stats;
+mod store;
+mod trace;
+
+pub use authority::AuthorityResponder;
+pub use byzantine::{Byzantine, ByzantineSettings, ByzantineSettingsBuilder};
+pub use churn::{Churn, ChurnSettings, ChurnSettingsBuilder};
+pub use clock::{Clock, VirtualClock};
+pub use filter::{ExpectError, MsgFilter, MsgKind};
+pub use gossip::GossipResponder;
+pub use intercept::{Action, Intercept, Interceptor};
+pub use link::LinkProfile;
+pub use partition::{NoLocalAgents, Partition};
+pub use receipts::{ReceiptResponder, ReceiptSettings, ReceiptSettingsBuilder};
+pub use record::{read_recording, Direction, Record, RecordedMsg, Replay, RECORDING_VERSION};
+pub use responder::{Reply, Responder};
+pub use runtime::{MockSender, Runtime, RuntimeReport, RuntimeSettings, RuntimeSettingsBuilder};
+pub use schedule::{clean, Phase, Schedule};
+pub use stats::{BufferStats, Count, Flow, Histogram, Stats};
+pub use store::SimulatedStore;
+pub use trace::{chrome_trace, write_chrome_trace};
 
 pub struct MockNetwork {
     mock: HolochainP2pMockChannel,
+    rng: fastrand::Rng,
+    clock: Clock,
+    /// The channel's scenario when it is applied here instead of by the channel.
+    base: Option<MockScenario>,
+    base_offline: HashMap<AgentPubKey, bool>,
+    schedule: Schedule,
+    schedule_start: Instant,
+    /// The phase the offline agents were chosen for.
+    offline_phase: Option<usize>,
+    offline: HashMap<AgentPubKey, bool>,
+    delayed: DelayQueue<Pending>,
+    /// Messages from simulated agents waiting on a delay.
+    delayed_sends: DelayQueue<AddressedHolochainP2pMockMsg>,
+    /// Messages ready to be handed to the conductor.
+    /// They stay here until the channel has taken them
+    /// so a cancelled send is tried again.
+    outgoing: VecDeque<AddressedHolochainP2pMockMsg>,
+    /// The message the responders are working on.
+    handling: Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)>,
+    /// Messages passed over by [`next_matching`](Self::next_matching).
+    unmatched: VecDeque<(AddressedHolochainP2pMockMsg, Option<MockRespond>)>,
+    /// The conductor has closed the channel.
+    closed: bool,
+    partition: Option<Partition>,
+    healed_at: Option<Instant>,
+    /// Agents that belong to the real conductor.
+    local_agents: HashSet<AgentPubKey>,
+    links: HashMap<AgentPubKey, LinkProfile>,
+    /// Bandwidth limit for links without their own.
+    bytes_per_second: Option<u64>,
+    pipes: HashMap<AgentPubKey, Pipes>,
+    /// Agents that have been taken offline.
+    offline_agents: HashSet<AgentPubKey>,
+    percent_duplicate_msg: f32,
+    percent_reorder_msg: f32,
+    reorder_window: Duration,
+    responders: Vec<Box<dyn Responder>>,
+    chain: Chain,
+    recorder: Option<Recorder>,
+    stats: SharedStats,
+    /// Slots used and capacity of the buffer from the conductor.
+    buffer_probe: Option<BufferProbe>,
+    /// Messages from [`MockSender`]s.
+    outbox: mpsc::UnboundedReceiver<(AddressedHolochainP2pMockMsg, Duration)>,
+    sender: MockSender,
+}
+
+pub(crate) type BufferProbe = Box<dyn Fn() -> (usize, usize) + Send + Sync>;
+
+/// Respond to a message from the real conductor.
+pub struct MockRespond {
+    respond: HolochainP2pMockRespond,
+    delay: Duration,
+    bytes_per_second: Option<u64>,
+    pipe: Pipe,
+    clock: Clock,
+    /// The simulated agent that is responding.
+    agent: AgentPubKey,
+    /// When the request arrived from the conductor.
+    received: Instant,
+    stats: SharedStats,
+    chain: Chain,
+    /// Where to record the response and the id of the request.
+    record: Option<(Recorder, u64)>,
+}
+
+/// How a single message is treated on its way through the network.
+#[derive(Default)]
+struct Conditions {
+    inbound_delay: Duration,
+    outbound_delay: Duration,
+    bytes_per_second: Option<u64>,
+}
+
+/// A message waiting on a delay.
+enum Pending {
+    /// From the real conductor to a simulated agent.
+    Deliver(AddressedHolochainP2pMockMsg, Option<MockRespond>),
+    /// From the real conductor, held back by an interceptor.
+    Intercepted(AddressedHolochainP2pMockMsg, Option<MockRespond>),
+}
+
+enum Event {
+    Delayed,
+    DelayedSend,
+    Send(AddressedHolochainP2pMockMsg, Duration),
+    Msg(
+        Option<(
+            AddressedHolochainP2pMockMsg,
+            Option<HolochainP2pMockRespond>,
+        )>,
+    ),
 }
 
 impl MockNetwork {
-    pub(crate) fn new(mock: HolochainP2pMockChannel) -> Self {
-        Self { mock }
+    pub(crate) fn new(mock: HolochainP2pMockChannel, settings: NetworkSettings) -> Self {
+        let base = settings
+            .mock_applies_scenario()
+            .then(|| settings.scenario());
+        let NetworkSettings {
+            seed,
+            clock,
+            schedule,
+            links,
+            bytes_per_second,
+            percent_duplicate_msg,
+            percent_reorder_msg,
+            reorder_window,
+            ..
+        } = settings;
+        let (sender, outbox) = MockSender::channel();
+        Self {
+            mock,
+            rng: seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            schedule_start: clock.now(),
+            clock,
+            base,
+            base_offline: HashMap::new(),
+            schedule,
+            offline_phase: None,
+            offline: HashMap::new(),
+            delayed: DelayQueue::new(),
+            delayed_sends: DelayQueue::new(),
+            outgoing: VecDeque::new(),
+            handling: None,
+            unmatched: VecDeque::new(),
+            closed: false,
+            partition: None,
+            healed_at: None,
+            local_agents: HashSet::new(),
+            links,
+            bytes_per_second,
+            pipes: HashMap::new(),
+            offline_agents: HashSet::new(),
+            percent_duplicate_msg,
+            percent_reorder_msg,
+            reorder_window,
+            responders: Vec::new(),
+            chain: Chain::default(),
+            recorder: None,
+            stats: Arc::new(Mutex::new(Stats::default())),
+            buffer_probe: None,
+            outbox,
+            sender,
+        }
+    }
+
+    /// Deliver this percent of messages twice.
+    /// The second copy arrives some time within the reorder window
+    /// and can't be responded to.
+    pub fn set_duplicate(&mut self, percent_duplicate_msg: f32) {
+        self.percent_duplicate_msg = percent_duplicate_msg;
+    }
+
+    /// Hold back this percent of messages by up to the window
+    /// so messages sent after them can overtake them.
+    pub fn set_reorder(&mut self, percent_reorder_msg: f32, reorder_window: Duration) {
+        self.percent_reorder_msg = percent_reorder_msg;
+        self.reorder_window = reorder_window;
+    }
+
+    /// Take a simulated agent offline or bring it back.
+    /// Offline agents don't receive or answer any messages.
+    pub fn set_online(&mut self, agent: &AgentPubKey, online: bool) {
+        if online {
+            self.offline_agents.remove(agent);
+        } else {
+            self.offline_agents.insert(agent.clone());
+        }
+    }
+
+    pub fn is_online(&self, agent: &AgentPubKey) -> bool {
+        !self.offline_agents.contains(agent)
+    }
+
+    /// Limit the bandwidth of every link that doesn't set its own.
+    pub fn set_bandwidth(&mut self, bytes_per_second: Option<u64>) {
+        self.bytes_per_second = bytes_per_second;
+    }
+
+    /// Set the link profile for a simulated agent.
+    pub fn set_link(&mut self, agent: AgentPubKey, link: LinkProfile) {
+        self.links.insert(agent, link);
+    }
+
+    /// Remove the link profile for a simulated agent.
+    pub fn remove_link(&mut self, agent: &AgentPubKey) -> Option<LinkProfile> {
+        self.links.remove(agent)
+    }
+
+    /// Split the network into groups that can't reach each other.
+    /// Replaces any current partition.
+    ///
+    /// The conductor's agents must be added with
+    /// [`add_local_agent`](Self::add_local_agent) first
+    /// or gossip would cross the partition.
+    pub fn partition(&mut self, mut partition: Partition) -> Result<(), NoLocalAgents> {
+        if self.local_agents.is_empty() {
+            return Err(NoLocalAgents);
+        }
+        partition.start(self.clock.now());
+        self.partition = Some(partition);
+        self.healed_at = None;
+        Ok(())
+    }
+
+    /// Heal the current partition now.
+    pub fn heal(&mut self) {
+        if self.partition.take().is_some() {
+            self.healed_at = Some(self.clock.now());
+        }
+    }
+
+    /// When the last partition healed.
+    /// Useful for measuring how long gossip takes to converge.
+    pub fn healed_at(&self) -> Option<Instant> {
+        match &self.partition {
+            Some(p) if p.is_healed(self.clock.now()) => p.heals_at(),
+            _ => self.healed_at,
+        }
+    }
+
+    /// The clock this network's delays run on.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Mark an agent as belonging to the real conductor.
+    /// Agents that send wire messages are marked automatically
+    /// but gossip doesn't say which agent it is from.
+    pub fn add_local_agent(&mut self, agent: AgentPubKey) {
+        self.local_agents.insert(agent);
+    }
+
+    /// Run messages through this interceptor.
+    /// Interceptors run in the order they were added.
+    pub fn add_interceptor(&mut self, interceptor: impl Interceptor + 'static) {
+        self.chain.push(Box::new(interceptor));
+    }
+
+    /// Send a message from a simulated agent to the real conductor.
+    /// Delayed sends go out while [`next`](Self::next) is being polled.
+    ///
+    /// If this is cancelled the message still goes out on the next poll.
+    pub async fn send(&mut self, msg: AddressedHolochainP2pMockMsg) {
+        self.send_after(msg, Duration::ZERO).await
+    }
+
+    /// A handle for sending messages to the real conductor
+    /// from tasks that don't own the network.
+    pub fn sender(&self) -> MockSender {
+        self.sender.clone()
+    }
+
+    /// Send a message from a simulated agent to the real conductor
+    /// after waiting this long on top of the network conditions.
+    pub async fn send_after(&mut self, msg: AddressedHolochainP2pMockMsg, after: Duration) {
+        self.queue(msg, after);
+        self.flush().await;
+    }
+
+    /// Run a message from a simulated agent through the network conditions
+    /// and queue it to be sent.
+    fn queue(&mut self, msg: AddressedHolochainP2pMockMsg, after: Duration) {
+        let intercepted = self.chain.run(Flow::ToConductor, false, msg);
+        self.queue_injected();
+        let (msg, held) = match intercepted {
+            Some(intercepted) => intercepted,
+            None => return,
+        };
+        let real = match &msg.msg {
+            HolochainP2pMockMsg::Wire { to_agent, .. } => Some(to_agent.clone()),
+            _ => None,
+        };
+        let mut conditions = Conditions {
+            bytes_per_second: self.bytes_per_second,
+            ..Default::default()
+        };
+        if !self.is_online(&msg.agent)
+            || self.is_partitioned(&msg.agent, real.as_ref())
+            || !self.apply_schedule(&msg.agent, &mut conditions)
+            || !self.apply_link(&msg.agent, &mut conditions)
+        {
+            let size = msg_size(&msg.msg);
+            self.stats.lock().unwrap().dropped(Flow::ToConductor, size);
+            return;
+        }
+        let now = self.clock.now();
+        let pipes = self.pipes.entry(msg.agent.clone()).or_default();
+        let delay = after
+            + held
+            + conditions.outbound_delay
+            + pipes
+                .outbound
+                .reserve(now, msg_size(&msg.msg), conditions.bytes_per_second)
+            + self.reorder_delay();
+        if let Some(again) = self.duplicate_delay() {
+            self.delayed_sends.push(now + delay + again, msg.clone());
+        }
+        if delay.is_zero() {
+            self.send_now(msg);
+        } else {
+            self.delayed_sends.push(now + delay, msg);
+        }
+    }
+
+    /// Queue anything the interceptors injected.
+    fn queue_injected(&mut self) {
+        for msg in self.chain.take_injected() {
+            self.send_now(msg);
+        }
+    }
+
+    /// Queue a message that has been through the network conditions.
+    fn send_now(&mut self, msg: AddressedHolochainP2pMockMsg) {
+        self.sent(&msg);
+        self.outgoing.push_back(msg);
+    }
+
+    /// Count and record a message on its way to the conductor.
+    fn sent(&self, msg: &AddressedHolochainP2pMockMsg) {
+        self.stats.lock().unwrap().count(
+            &msg.agent,
+            MsgKind::of(&msg.msg),
+            Flow::ToConductor,
+            msg_size(&msg.msg),
+        );
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&msg.agent, Direction::ToConductor, &msg.msg);
+        }
+    }
+
+    /// Hand the queued messages to the conductor.
+    async fn flush(&mut self) {
+        while let Some(msg) = self.outgoing.front() {
+            self.mock.send(msg.clone()).await;
+            self.outgoing.pop_front();
+        }
+    }
+
+    /// Send a message from another real conductor to this one.
+    /// The network conditions were applied by the sender's network.
+    pub(crate) fn forward(&mut self, msg: AddressedHolochainP2pMockMsg) {
+        self.send_now(msg);
+    }
+
+    /// Send a request from another real conductor to this one
+    /// and pass the response back through the sender's network.
+    ///
+    /// The request is sent and answered on its own task so it is sent once
+    /// however often [`next`](Self::next) is cancelled,
+    /// and this network keeps taking messages while it waits.
+    pub(crate) fn forward_request(
+        &mut self,
+        msg: AddressedHolochainP2pMockMsg,
+        respond: MockRespond,
+    ) {
+        self.sent(&msg);
+        let request = self.mock.request(msg);
+        tokio::spawn(async move {
+            let response = match request.await {
+                Ok(response) => response,
+                Err(e) => HolochainP2pMockMsg::Failure(format!("{:?}", e)),
+            };
+            respond.respond(response);
+        });
+    }
+
+    /// Queue everything the [`MockSender`]s have sent so far.
+    fn drain_outbox(&mut self) {
+        while let Ok((msg, after)) = self.outbox.try_recv() {
+            self.queue(msg, after);
+        }
+    }
+
+    /// Keep sending messages from [`MockSender`]s and delayed messages
+    /// without taking any messages from the conductor.
+    /// This never returns so run it alongside something that does.
+    pub(crate) async fn pump_sends(&mut self) {
+        loop {
+            self.flush().await;
+            let due = self.delayed_sends.next_due();
+            let clock = &self.clock;
+            let delayed = clock.sleep_until(due.unwrap_or_else(|| clock.now()));
+            let event = tokio::select! {
+                biased;
+                _ = delayed, if due.is_some() => Event::DelayedSend,
+                Some((msg, after)) = self.outbox.recv() => Event::Send(msg, after),
+            };
+            self.handle_send(event);
+        }
+    }
+
+    /// Send everything the [`MockSender`]s have sent
+    /// and wait for every delayed message to go out.
+    pub(crate) async fn drain_sends(&mut self) {
+        loop {
+            self.drain_outbox();
+            self.flush().await;
+            match self.delayed_sends.next_due() {
+                Some(due) => {
+                    self.clock.sleep_until(due).await;
+                    self.handle_send(Event::DelayedSend);
+                }
+                None => return,
+            }
+        }
+    }
+
+    fn handle_send(&mut self, event: Event) {
+        match event {
+            Event::DelayedSend => {
+                if let Some(msg) = self.delayed_sends.pop() {
+                    self.send_now(msg);
+                }
+            }
+            Event::Send(msg, after) => self.queue(msg, after),
+            _ => (),
+        }
+    }
+
+    /// A snapshot of the traffic so far.
+    pub fn stats(&self) -> Stats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub fn reset_stats(&mut self) {
+        *self.stats.lock().unwrap() = Stats::default();
+    }
+
+    pub(crate) fn set_buffer_probe(&mut self, probe: BufferProbe) {
+        self.buffer_probe = Some(probe);
+    }
+
+    /// Check how full the buffer from the conductor is
+    /// and warn if the conductor is now blocked on it.
+    pub(crate) fn sample_buffer(&self) {
+        let (used, capacity) = match &self.buffer_probe {
+            Some(probe) => probe(),
+            None => return,
+        };
+        if self.stats.lock().unwrap().buffer_used(used, capacity) {
+            holochain::tracing::warn!(
+                capacity,
+                "The conductor is blocked on a full mock network buffer"
+            );
+        }
+    }
+
+    /// Record every message from now on to this file.
+    /// Replaces any recording already in progress.
+    pub fn record(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::create(path, self.clock.clone())?);
+        Ok(())
+    }
+
+    /// Stop recording and flush the file.
+    /// Responses still in flight are written when they are sent.
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Play a recording back to the conductor starting now.
+    /// Recorded sends skip the network conditions because they were
+    /// already applied when the recording was made.
+    pub fn replay(&mut self, replay: Replay) {
+        let now = self.clock.now();
+        let (sends, responder) = replay.into_parts();
+        for (at, msg) in sends {
+            self.delayed_sends.push(now + at, msg);
+        }
+        self.add_responder(responder);
+    }
+
+    /// Replace the current schedule.
+    /// The new schedule starts now.
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+        self.schedule_start = self.clock.now();
+        self.offline_phase = None;
+        self.offline.clear();
+    }
+
+    /// Answer messages with this responder before they reach the caller.
+    /// Responders are tried in the order they were added.
+    pub fn add_responder(&mut self, responder: impl Responder + 'static) {
+        self.responders.push(Box::new(responder));
+    }
+
+    /// The next message for the caller.
+    ///
+    /// This is cancel safe.
+    /// Sends in progress finish on the next poll and a message
+    /// the responders were working on is handled again.
+    pub async fn next(&mut self) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        match self.unmatched.pop_front() {
+            Some(msg) => Some(msg),
+            None => self.next_handled().await,
+        }
+    }
+
+    /// The next message from the conductor that no responder handled.
+    async fn next_handled(
+        &mut self,
+    ) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        loop {
+            if self.handling.is_none() {
+                let (msg, mut respond) = self.next_msg().await?;
+                self.queue_injected();
+                if let Some(recorder) = &self.recorder {
+                    let id = recorder.record(&msg.agent, Direction::FromConductor, &msg.msg);
+                    if let Some(respond) = &mut respond {
+                        respond.record = Some((recorder.clone(), id));
+                    }
+                }
+                self.handling = Some((msg, respond));
+            }
+            let mut reply = None;
+            if let Some((msg, _)) = &self.handling {
+                for responder in &mut self.responders {
+                    reply = responder.handle(msg).await;
+                    if reply.is_some() {
+                        break;
+                    }
+                }
+            }
+            let (msg, respond) = self.handling.take()?;
+            let reply = match reply {
+                Some(reply) => reply,
+                None => return Some((msg, respond)),
+            };
+            if let (Some(respond), Some(response)) = (respond, reply.respond) {
+                respond.respond(response);
+            }
+            for msg in reply.send {
+                self.queue(msg, reply.delay);
+            }
+        }
+    }
+
+    /// The next message that matches the filter.
+    /// Messages that don't match are kept in order
+    /// for later calls to [`next`](Self::next).
+    pub async fn next_matching(
+        &mut self,
+        filter: impl Into<MsgFilter>,
+    ) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        let filter = filter.into();
+        if let Some(i) = self
+            .unmatched
+            .iter()
+            .position(|(msg, _)| filter.matches(msg))
+        {
+            return self.unmatched.remove(i);
+        }
+        loop {
+            let (msg, respond) = self.next_handled().await?;
+            if filter.matches(&msg) {
+                return Some((msg, respond));
+            }
+            self.unmatched.push_back((msg, respond));
+        }
+    }
+
+    /// The next message of this kind.
+    pub async fn next_kind(
+        &mut self,
+        kind: MsgKind,
+    ) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        self.next_matching(kind).await
+    }
+
+    /// The next message to this simulated agent.
+    pub async fn next_to(
+        &mut self,
+        agent: AgentPubKey,
+    ) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        self.next_matching(MsgFilter::new().to_agent(agent)).await
+    }
+
+    /// Only the messages that match the filter.
+    /// Messages that don't match are kept for later calls to [`next`](Self::next)
+    /// so drain them if the stream runs for a long time.
+    pub fn filtered(
+        &mut self,
+        filter: impl Into<MsgFilter>,
+    ) -> impl Stream<Item = (AddressedHolochainP2pMockMsg, Option<MockRespond>)> + '_ {
+        futures::stream::unfold((self, filter.into()), |(network, filter)| async move {
+            let msg = network.next_matching(filter.clone()).await?;
+            Some((msg, (network, filter)))
+        })
+    }
+
+    /// Wait for a message that matches the filter.
+    /// Messages that don't match are kept for later calls to [`next`](Self::next),
+    /// including any that arrived before the timeout.
+    pub async fn expect_message(
+        &mut self,
+        filter: impl Into<MsgFilter>,
+        timeout: Duration,
+    ) -> Result<(AddressedHolochainP2pMockMsg, Option<MockRespond>), ExpectError> {
+        let filter = filter.into();
+        let clock = self.clock.clone();
+        tokio::select! {
+            msg = self.next_matching(filter.clone()) => msg.ok_or(ExpectError::Closed { filter }),
+            _ = clock.sleep(timeout) => Err(ExpectError::Timeout { filter, after: timeout }),
+        }
+    }
+
+    /// The next message from the conductor that made it through the network.
+    /// Nothing here is lost if it is cancelled between awaits.
+    async fn next_msg(&mut self) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        loop {
+            if self.closed {
+                self.drain_outbox();
+            }
+            self.flush().await;
+            if self.closed && self.delayed.is_empty() && self.delayed_sends.is_empty() {
+                return None;
+            }
+            let due = self.delayed.next_due();
+            let send_due = self.delayed_sends.next_due();
+            let clock = &self.clock;
+            let delayed = clock.sleep_until(due.unwrap_or_else(|| clock.now()));
+            let delayed_send = clock.sleep_until(send_due.unwrap_or_else(|| clock.now()));
+            let open = !self.closed;
+            let event = tokio::select! {
+                biased;
+                _ = delayed, if due.is_some() => Event::Delayed,
+                _ = delayed_send, if send_due.is_some() => Event::DelayedSend,
+                Some((msg, after)) = self.outbox.recv(), if open => Event::Send(msg, after),
+                msg = self.mock.next(), if open => Event::Msg(msg),
+            };
+            match event {
+                Event::Delayed => match self.delayed.pop() {
+                    Some(Pending::Deliver(msg, respond)) => {
+                        if let Some(msg) = self.intercept(msg, respond) {
+                            return Some(msg);
+                        }
+                    }
+                    Some(Pending::Intercepted(msg, respond)) => return Some((msg, respond)),
+                    None => (),
+                },
+                Event::DelayedSend | Event::Send(..) => self.handle_send(event),
+                Event::Msg(Some((msg, respond))) => {
+                    let size = msg_size(&msg.msg);
+                    self.sample_buffer();
+                    let real = match &msg.msg {
+                        HolochainP2pMockMsg::Wire { from_agent, .. } => {
+                            self.local_agents.insert(from_agent.clone());
+                            Some(from_agent.clone())
+                        }
+                        _ => None,
+                    };
+                    if !self.is_online(&msg.agent) || self.is_partitioned(&msg.agent, real.as_ref())
+                    {
+                        self.stats
+                            .lock()
+                            .unwrap()
+                            .dropped(Flow::FromConductor, size);
+                        continue;
+                    }
+                    if let Some((msg, respond)) = self.route(msg, respond, size) {
+                        if let Some(msg) = self.intercept(msg, respond) {
+                            return Some(msg);
+                        }
+                    }
+                }
+                // Anything still delayed is delivered before returning `None`.
+                Event::Msg(None) => self.closed = true,
+            }
+        }
+    }
+
+    /// Run a message from the conductor through the interceptors.
+    /// Returns the message if it is ready to be delivered now.
+    fn intercept(
+        &mut self,
+        msg: AddressedHolochainP2pMockMsg,
+        respond: Option<MockRespond>,
+    ) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        let (msg, held) = self.chain.run(Flow::FromConductor, false, msg)?;
+        if held.is_zero() {
+            Some((msg, respond))
+        } else {
+            let due = self.clock.now() + held;
+            self.delayed.push(due, Pending::Intercepted(msg, respond));
+            None
+        }
     }
 
-    pub async fn next(
+    /// Is the real conductor on the other side of a partition from this simulated agent?
+    /// The real agent is used if the message says which one it is.
+    fn is_partitioned(&mut self, agent: &AgentPubKey, real: Option<&AgentPubKey>) -> bool {
+        let now = self.clock.now();
+        let partition = match &self.partition {
+            Some(partition) => partition,
+            None => return false,
+        };
+        if partition.is_healed(now) {
+            self.healed_at = partition.heals_at();
+            self.partition = None;
+            return false;
+        }
+        match real {
+            Some(real) => !partition.can_reach(real, agent),
+            None => !self
+                .local_agents
+                .iter()
+                .any(|local| partition.can_reach(local, agent)),
+        }
+    }
+
+    /// Drop, delay or pass through a message.
+    fn route(
         &mut self,
-    ) -> Option<(
-        AddressedHolochainP2pMockMsg,
-        Option<HolochainP2pMockRespond>,
-    )> {
-        self.mock.next().await
+        msg: AddressedHolochainP2pMockMsg,
+        respond: Option<HolochainP2pMockRespond>,
+        size: usize,
+    ) -> Option<(AddressedHolochainP2pMockMsg, Option<MockRespond>)> {
+        let mut conditions = Conditions {
+            bytes_per_second: self.bytes_per_second,
+            ..Default::default()
+        };
+        if !self.apply_schedule(&msg.agent, &mut conditions)
+            || !self.apply_link(&msg.agent, &mut conditions)
+        {
+            self.stats
+                .lock()
+                .unwrap()
+                .dropped(Flow::FromConductor, size);
+            return None;
+        }
+        self.stats.lock().unwrap().count(
+            &msg.agent,
+            MsgKind::of(&msg.msg),
+            Flow::FromConductor,
+            size,
+        );
+        let Conditions {
+            inbound_delay,
+            outbound_delay,
+            bytes_per_second,
+        } = conditions;
+        let now = self.clock.now();
+        let pipes = self.pipes.entry(msg.agent.clone()).or_default();
+        let inbound_delay = inbound_delay + pipes.inbound.reserve(now, size, bytes_per_second);
+        let respond = respond.map(|respond| MockRespond {
+            respond,
+            delay: outbound_delay,
+            bytes_per_second,
+            pipe: pipes.outbound.clone(),
+            clock: self.clock.clone(),
+            agent: msg.agent.clone(),
+            received: now,
+            stats: self.stats.clone(),
+            chain: self.chain.clone(),
+            record: None,
+        });
+        let inbound_delay = inbound_delay + self.reorder_delay();
+        if let Some(again) = self.duplicate_delay() {
+            self.delayed.push(
+                now + inbound_delay + again,
+                Pending::Deliver(msg.clone(), None),
+            );
+        }
+        if inbound_delay.is_zero() {
+            Some((msg, respond))
+        } else {
+            self.delayed
+                .push(now + inbound_delay, Pending::Deliver(msg, respond));
+            None
+        }
+    }
+
+    /// Extra delay if this message is being reordered.
+    fn reorder_delay(&self) -> Duration {
+        if chance(&self.rng, self.percent_reorder_msg) {
+            random_delay(&self.rng, &(Duration::ZERO..self.reorder_window))
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// How long after the original a duplicate arrives
+    /// if this message is being duplicated.
+    fn duplicate_delay(&self) -> Option<Duration> {
+        chance(&self.rng, self.percent_duplicate_msg)
+            .then(|| random_delay(&self.rng, &(Duration::ZERO..self.reorder_window)))
+    }
+
+    /// Add the current phase to the conditions.
+    /// Returns false if the message is dropped.
+    fn apply_schedule(&mut self, agent: &AgentPubKey, conditions: &mut Conditions) -> bool {
+        if let Some(base) = &self.base {
+            if !apply_scenario(&self.rng, base, &mut self.base_offline, agent, conditions) {
+                return false;
+            }
+        }
+        let elapsed = self
+            .clock
+            .now()
+            .saturating_duration_since(self.schedule_start);
+        let (phase, scenario) = match self.schedule.at(elapsed) {
+            Some((phase, scenario)) => (phase, scenario),
+            None => return true,
+        };
+        if self.offline_phase != Some(phase) {
+            self.offline_phase = Some(phase);
+            self.offline.clear();
+        }
+        apply_scenario(&self.rng, scenario, &mut self.offline, agent, conditions)
+    }
+
+    /// Add the agent's link to the conditions.
+    /// Returns false if the message is lost.
+    fn apply_link(&mut self, agent: &AgentPubKey, conditions: &mut Conditions) -> bool {
+        let link = match self.links.get(agent) {
+            Some(link) => link,
+            None => return true,
+        };
+        if chance(&self.rng, link.percent_loss) {
+            return false;
+        }
+        conditions.inbound_delay += random_delay(&self.rng, &link.latency);
+        conditions.outbound_delay += random_delay(&self.rng, &link.latency);
+        if link.bytes_per_second.is_some() {
+            conditions.bytes_per_second = link.bytes_per_second;
+        }
+        true
+    }
+}
+
+impl MockRespond {
+    pub fn respond(self, msg: HolochainP2pMockMsg) {
+        let Self {
+            respond,
+            delay,
+            bytes_per_second,
+            pipe,
+            clock,
+            agent,
+            received,
+            stats,
+            chain,
+            record,
+        } = self;
+        let addressed = AddressedHolochainP2pMockMsg {
+            agent: agent.clone(),
+            msg,
+        };
+        let (msg, held) = match chain.run(Flow::ToConductor, true, addressed) {
+            Some((addressed, held)) => (addressed.msg, held),
+            None => return,
+        };
+        if let Some((recorder, request)) = record {
+            recorder.record(&agent, Direction::Response { request }, &msg);
+        }
+        let now = clock.now();
+        let size = msg_size(&msg);
+        let delay = held + delay + pipe.reserve(now, size, bytes_per_second);
+        {
+            let mut stats = stats.lock().unwrap();
+            stats.count(&agent, MsgKind::of(&msg), Flow::ToConductor, size);
+            stats
+                .response_latency
+                .record((now + delay).saturating_duration_since(received));
+        }
+        if delay.is_zero() {
+            respond.respond(msg);
+        } else {
+            tokio::spawn(async move {
+                clock.sleep(delay).await;
+                respond.respond(msg);
+            });
+        }
+    }
+}
+
+/// Apply a scenario to the conditions.
+/// Returns false if the message is dropped.
+fn apply_scenario(
+    rng: &fastrand::Rng,
+    scenario: &MockScenario,
+    offline: &mut HashMap<AgentPubKey, bool>,
+    agent: &AgentPubKey,
+    conditions: &mut Conditions,
+) -> bool {
+    let offline = *offline
+        .entry(agent.clone())
+        .or_insert_with(|| chance(rng, scenario.percent_offline));
+    if offline || chance(rng, scenario.percent_drop_msg) {
+        return false;
     }
+    conditions.inbound_delay += random_delay(rng, &scenario.inbound_delay_range);
+    conditions.outbound_delay += random_delay(rng, &scenario.outbound_delay_range);
+    true
 }
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use holochain_cascade::authority::{
+    handle_get_agent_activity, handle_get_element, handle_get_entry, handle_get_links,
+};
+use holochain_cascade::error::CascadeResult;
+use holochain_p2p::mock_network::*;
+use holochain_p2p::wire::{WireMessage, WireOps};
+use holochain_p2p::AgentPubKeyExt;
+use holochain_state::prelude::{DatabaseResult, DbKindDht, DbRead};
+use holochain_state::test_utils::{test_dht_db, TestDb};
+use holochain_types::prelude::*;
+use kitsune_p2p::agent_store::AgentInfoSigned;
+use kitsune_p2p_types::dht_arc::{DhtArc, DhtLocation};
+
+use crate::insert_element_as_authority;
+
+use super::responder::{Reply, Responder};
+
+/// Answers gets from the real conductor as if each simulated agent
+/// was an authority for the generated data inside its arc.
+///
+/// All the data is held in one database.
+/// Requests for a basis outside the agent's arc are answered from an
+/// empty database so the responses look like an authority holding nothing.
+pub struct AuthorityResponder {
+    dna_hash: DnaHash,
+    arcs: HashMap<AgentPubKey, DhtArc>,
+    db: TestDb<DbKindDht>,
+    empty: TestDb<DbKindDht>,
+}
+
+impl AuthorityResponder {
+    /// Hold the generated chains for the simulated agents in `peer_data`.
+    pub fn new(
+        dna_hash: DnaHash,
+        data: &HashMap<AgentPubKey, Vec<Element>>,
+        peer_data: &[AgentInfoSigned],
+    ) -> Self {
+        let db = test_dht_db();
+        db.to_db()
+            .conn()
+            .unwrap()
+            .with_commit_sync(|txn| {
+                for element in data.values().flatten() {
+                    insert_element_as_authority(txn, element);
+                }
+                DatabaseResult::Ok(())
+            })
+            .unwrap();
+        let arcs = peer_data
+            .iter()
+            .map(|info| (AgentPubKey::from_kitsune(&info.agent), info.storage_arc))
+            .collect();
+        Self {
+            dna_hash,
+            arcs,
+            db,
+            empty: test_dht_db(),
+        }
+    }
+
+    /// The database this agent answers from for this basis.
+    fn db_for(&self, agent: &AgentPubKey, basis: DhtLocation) -> DbRead<DbKindDht> {
+        if self.arcs[agent].contains(basis) {
+            self.db.to_db().into()
+        } else {
+            self.empty.to_db().into()
+        }
+    }
+
+    async fn get(&self, agent: &AgentPubKey, msg: &WireMessage) -> Option<HolochainP2pMockMsg> {
+        let response = match msg.clone() {
+            WireMessage::Get { dht_hash, options } => {
+                let db = self.db_for(agent, dht_hash.get_loc());
+                match dht_hash.into_primitive() {
+                    AnyDhtHashPrimitive::Entry(hash) => call_resp(
+                        handle_get_entry(db, hash, options)
+                            .await
+                            .map(WireOps::Entry),
+                    ),
+                    AnyDhtHashPrimitive::Header(hash) => call_resp(
+                        handle_get_element(db, hash, options)
+                            .await
+                            .map(WireOps::Element),
+                    ),
+                }
+            }
+            WireMessage::GetLinks { link_key, options } => {
+                let db = self.db_for(agent, link_key.base.get_loc());
+                call_resp(handle_get_links(db, link_key, options).await)
+            }
+            WireMessage::GetAgentActivity {
+                agent: author,
+                query,
+                options,
+            } => {
+                let db = self.db_for(agent, author.get_loc());
+                call_resp(handle_get_agent_activity(db, author, query, options).await)
+            }
+            _ => return None,
+        };
+        Some(response)
+    }
+}
+
+fn call_resp<T>(response: CascadeResult<T>) -> HolochainP2pMockMsg
+where
+    T: TryInto<SerializedBytes, Error = SerializedBytesError>,
+{
+    let bytes = response.map_err(|e| e.to_string()).and_then(|r| {
+        r.try_into()
+            .map_err(|e: SerializedBytesError| e.to_string())
+    });
+    match bytes {
+        Ok(bytes) => HolochainP2pMockMsg::CallResp(bytes),
+        Err(e) => HolochainP2pMockMsg::Failure(e),
+    }
+}
+
+impl Responder for AuthorityResponder {
+    fn handle<'a>(
+        &'a mut self,
+        msg: &'a AddressedHolochainP2pMockMsg,
+    ) -> BoxFuture<'a, Option<Reply>> {
+        async move {
+            let wire = match &msg.msg {
+                HolochainP2pMockMsg::Wire { dna, msg: wire, .. } if *dna == self.dna_hash => wire,
+                _ => return None,
+            };
+            if !self.arcs.contains_key(&msg.agent) {
+                return None;
+            }
+            let response = self.get(&msg.agent, wire).await?;
+            Some(Reply::respond(response))
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use derive_builder::Builder;
+use holochain_keystore::MetaLairClient;
+use holochain_p2p::mock_network::*;
+use holochain_p2p::wire::WireOps;
+use holochain_p2p::{AgentPubKeyExt, WireDhtOpData};
+use holochain_types::dht_op::produce_ops_from_element;
+use holochain_types::prelude::*;
+use kitsune_p2p::agent_store::AgentInfoSigned;
+use kitsune_p2p::gossip::sharded_gossip::ShardedGossipWire;
+use kitsune_p2p::KitsuneAgent;
+use kitsune_p2p_types::dht_arc::{ArcInterval, MAX_HALF_LENGTH};
+use kitsune_p2p_types::KOpData;
+
+use crate::agent_info::resign_agent_info_with_arc;
+use crate::keys::SeededKeys;
+
+use super::gossip::encode_op;
+use super::intercept::{Action, Intercept, Interceptor};
+use super::schedule::chance;
+use super::stats::Flow;
+
+/// How many unrequested ops are added to a message.
+const UNREQUESTED_OPS: usize = 10;
+
+/// Simulated agents that lie.
+///
+/// Add this as an interceptor to change what the responders send for these agents.
+/// Ops they send through gossip or get responses are corrupted,
+/// gossip is padded with ops nobody asked for
+/// and gossip accepts claim the full arc
+/// with agent info signed again to match.
+pub struct Byzantine {
+    agents: HashSet<AgentPubKey>,
+    /// Agent info claiming the full arc to send in place of the honest info.
+    full_arc_info: HashMap<Arc<KitsuneAgent>, AgentInfoSigned>,
+    /// Ops to send when nobody asked for them.
+    pool: Vec<KOpData>,
+    settings: ByzantineSettings,
+    rng: fastrand::Rng,
+}
+
+#[derive(Builder, Clone)]
+pub struct ByzantineSettings {
+    /// Percent of ops that have their signature broken.
+    #[builder(default = "30.0")]
+    pub percent_bad_signature: f32,
+    /// Percent of ops with an entry that have the entry swapped
+    /// so it no longer matches its hash.
+    #[builder(default = "30.0")]
+    pub percent_bad_hash: f32,
+    /// Percent of gossip messages that have extra ops added.
+    #[builder(default = "30.0")]
+    pub percent_unrequested: f32,
+    /// Claim the full arc when accepting gossip.
+    #[builder(default = "true")]
+    pub wrong_arcs: bool,
+    #[builder(default)]
+    pub seed: Option<u64>,
+    /// Keys for agents that aren't in the keystore.
+    #[builder(default)]
+    pub seeded: SeededKeys,
+}
+
+impl Byzantine {
+    /// Make the agents with this agent info malicious.
+    /// Unrequested ops are taken from `data`.
+    ///
+    /// With wrong arcs the agent info is signed again claiming the full arc
+    /// so the agents' keys must be in the keystore or in the settings.
+    pub async fn new(
+        keystore: &MetaLairClient,
+        agents: &[AgentInfoSigned],
+        data: &HashMap<AgentPubKey, Vec<Element>>,
+        settings: ByzantineSettingsBuilder,
+    ) -> Self {
+        let settings = settings.build().unwrap();
+        let mut full_arc_info = HashMap::new();
+        if settings.wrong_arcs {
+            for info in agents {
+                let agent = AgentPubKey::from_kitsune(&info.agent);
+                let key = settings.seeded.get(&agent);
+                let full = resign_agent_info_with_arc(keystore, info, key, MAX_HALF_LENGTH).await;
+                full_arc_info.insert(info.agent.clone(), full);
+            }
+        }
+        let pool = data
+            .values()
+            .flatten()
+            .flat_map(|element| produce_ops_from_element(element).unwrap())
+            .map(encode_op)
+            .collect();
+        Self {
+            agents: agents
+                .iter()
+                .map(|info| AgentPubKey::from_kitsune(&info.agent))
+                .collect(),
+            full_arc_info,
+            pool,
+            rng: settings
+                .seed
+                .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            settings,
+        }
+    }
+
+    fn corrupt_op(&self, op: &mut DhtOp) {
+        if chance(&self.rng, self.settings.percent_bad_hash) {
+            if let Some(entry) = entry_mut(op) {
+                *entry = self.random_entry();
+                return;
+            }
+        }
+        if chance(&self.rng, self.settings.percent_bad_signature) {
+            self.corrupt_signature(signature_mut(op));
+        }
+    }
+
+    fn corrupt_signature(&self, signature: &mut Signature) {
+        let i = self.rng.usize(..signature.0.len());
+        signature.0[i] ^= 0xff;
+    }
+
+    fn random_entry(&self) -> Entry {
+        let bytes: Vec<u8> = std::iter::repeat_with(|| self.rng.u8(..))
+            .take(32)
+            .collect();
+        Entry::app(SerializedBytes::from(UnsafeBytes::from(bytes))).unwrap()
+    }
+
+    fn corrupt_gossip(&self, gossip: ShardedGossipWire) -> ShardedGossipWire {
+        match gossip {
+            ShardedGossipWire::MissingOps(mut missing) => {
+                for op in &mut missing.ops {
+                    *op = self.corrupt_encoded(op);
+                }
+                if !self.pool.is_empty() && chance(&self.rng, self.settings.percent_unrequested) {
+                    for _ in 0..UNREQUESTED_OPS {
+                        let op = &self.pool[self.rng.usize(..self.pool.len())];
+                        missing.ops.push(op.clone());
+                    }
+                }
+                ShardedGossipWire::MissingOps(missing)
+            }
+            ShardedGossipWire::Accept(mut accept) if self.settings.wrong_arcs => {
+                accept.intervals = vec![ArcInterval::Full];
+                for info in &mut accept.agent_list {
+                    if let Some(full) = self.full_arc_info.get(&info.agent) {
+                        *info = full.clone();
+                    }
+                }
+                ShardedGossipWire::Accept(accept)
+            }
+            gossip => gossip,
+        }
+    }
+
+    fn corrupt_encoded(&self, op: &KOpData) -> KOpData {
+        match WireDhtOpData::decode(op.0.clone()) {
+            Ok(mut data) => {
+                self.corrupt_op(&mut data.op_data);
+                encode_op(data.op_data)
+            }
+            Err(_) => op.clone(),
+        }
+    }
+
+    /// Corrupt a get response.
+    fn corrupt_response(&self, resp: SerializedBytes) -> SerializedBytes {
+        let mut ops = match WireOps::try_from(resp.clone()) {
+            Ok(ops) => ops,
+            Err(_) => return resp,
+        };
+        match &mut ops {
+            WireOps::Element(element) => {
+                if let Some(header) = &mut element.header {
+                    if chance(&self.rng, self.settings.percent_bad_signature) {
+                        self.corrupt_signature(&mut header.data.1);
+                    }
+                }
+                if element.entry.is_some() && chance(&self.rng, self.settings.percent_bad_hash) {
+                    element.entry = Some(self.random_entry());
+                }
+            }
+            WireOps::Entry(entry) => {
+                for create in &mut entry.creates {
+                    if chance(&self.rng, self.settings.percent_bad_signature) {
+                        self.corrupt_signature(&mut create.data.signature);
+                    }
+                }
+                if let Some(data) = &mut entry.entry {
+                    if chance(&self.rng, self.settings.percent_bad_hash) {
+                        data.entry = self.random_entry();
+                    }
+                }
+            }
+        }
+        SerializedBytes::try_from(ops).unwrap_or(resp)
+    }
+}
+
+fn signature_mut(op: &mut DhtOp) -> &mut Signature {
+    match op {
+        DhtOp::StoreElement(signature, ..)
+        | DhtOp::StoreEntry(signature, ..)
+        | DhtOp::RegisterAgentActivity(signature, ..)
+        | DhtOp::RegisterUpdatedContent(signature, ..)
+        | DhtOp::RegisterUpdatedElement(signature, ..)
+        | DhtOp::RegisterDeletedBy(signature, ..)
+        | DhtOp::RegisterDeletedEntryHeader(signature, ..)
+        | DhtOp::RegisterAddLink(signature, ..)
+        | DhtOp::RegisterRemoveLink(signature, ..) => signature,
+    }
+}
+
+fn entry_mut(op: &mut DhtOp) -> Option<&mut Entry> {
+    match op {
+        DhtOp::StoreElement(_, _, entry)
+        | DhtOp::RegisterUpdatedContent(_, _, entry)
+        | DhtOp::RegisterUpdatedElement(_, _, entry) => entry.as_deref_mut(),
+        DhtOp::StoreEntry(_, _, entry) => Some(entry),
+        _ => None,
+    }
+}
+
+impl Interceptor for Byzantine {
+    fn intercept(&mut self, cx: &mut Intercept<'_>, msg: AddressedHolochainP2pMockMsg) -> Action {
+        if cx.flow != Flow::ToConductor || !self.agents.contains(&msg.agent) {
+            return Action::Pass(msg);
+        }
+        let AddressedHolochainP2pMockMsg { agent, msg } = msg;
+        let msg = match msg {
+            HolochainP2pMockMsg::Gossip {
+                dna,
+                module,
+                gossip: GossipProtocol::Sharded(gossip),
+            } => HolochainP2pMockMsg::Gossip {
+                dna,
+                module,
+                gossip: GossipProtocol::Sharded(self.corrupt_gossip(gossip)),
+            },
+            HolochainP2pMockMsg::CallResp(resp) if cx.response => {
+                HolochainP2pMockMsg::CallResp(self.corrupt_response(resp))
+            }
+            msg => msg,
+        };
+        Action::Pass(AddressedHolochainP2pMockMsg { agent, msg })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kitsune_p2p::gossip::sharded_gossip::MissingOpsStatus;
+    use kitsune_p2p::GossipModuleType;
+
+    use super::*;
+    use crate::network::intercept::Chain;
+    use crate::test_utils::agent;
+    use crate::types::make;
+
+    /// Agent 1 lies about everything it sends.
+    fn byzantine(settings: &ByzantineSettingsBuilder, pool: Vec<KOpData>) -> Byzantine {
+        Byzantine {
+            agents: [agent(1)].into_iter().collect(),
+            full_arc_info: HashMap::new(),
+            pool,
+            rng: fastrand::Rng::with_seed(1),
+            settings: settings.build().unwrap(),
+        }
+    }
+
+    fn store_entry() -> DhtOp {
+        let entry = Entry::App(make(|u| u.arbitrary()));
+        let mut create: Create = make(|u| u.arbitrary());
+        create.entry_type = EntryType::App(make(|u| u.arbitrary()));
+        create.entry_hash = EntryHash::with_data_sync(&entry);
+        DhtOp::StoreEntry(
+            Signature([1; 64]),
+            NewEntryHeader::Create(create),
+            Box::new(entry),
+        )
+    }
+
+    fn missing_ops(from: u8, op: DhtOp) -> AddressedHolochainP2pMockMsg {
+        AddressedHolochainP2pMockMsg {
+            agent: agent(from),
+            msg: HolochainP2pMockMsg::Gossip {
+                dna: DnaHash::from_raw_32(vec![0; 32]),
+                module: GossipModuleType::ShardedRecent,
+                gossip: GossipProtocol::Sharded(ShardedGossipWire::missing_ops(
+                    vec![encode_op(op)],
+                    MissingOpsStatus::AllComplete as u8,
+                )),
+            },
+        }
+    }
+
+    fn ops(msg: AddressedHolochainP2pMockMsg) -> Vec<DhtOp> {
+        match msg.msg {
+            HolochainP2pMockMsg::Gossip {
+                gossip: GossipProtocol::Sharded(ShardedGossipWire::MissingOps(missing)),
+                ..
+            } => missing
+                .ops
+                .iter()
+                .map(|op| WireDhtOpData::decode(op.0.clone()).unwrap().op_data)
+                .collect(),
+            _ => panic!("not missing ops"),
+        }
+    }
+
+    #[test]
+    fn only_its_own_gossip_to_the_conductor_is_corrupted() {
+        let op = store_entry();
+        let mut settings = ByzantineSettingsBuilder::default();
+        settings.percent_bad_hash(100.0).percent_unrequested(100.0);
+        let chain = Chain::default();
+        chain.push(Box::new(byzantine(&settings, vec![encode_op(op.clone())])));
+        let run = |flow, from| {
+            let (msg, _) = chain
+                .run(flow, false, missing_ops(from, op.clone()))
+                .unwrap();
+            ops(msg)
+        };
+
+        let lied = run(Flow::ToConductor, 1);
+        assert_eq!(lied.len(), 1 + UNREQUESTED_OPS);
+        assert_ne!(lied[0], op);
+        assert!(lied[1..].iter().all(|o| *o == op));
+        assert_eq!(run(Flow::ToConductor, 2), vec![op.clone()]);
+        assert_eq!(run(Flow::FromConductor, 1), vec![op.clone()]);
+    }
+
+    #[test]
+    fn bad_hashes_swap_the_entry_and_bad_signatures_break_the_signature() {
+        let mut op = store_entry();
+        let entry = entry_mut(&mut op).cloned();
+        let mut settings = ByzantineSettingsBuilder::default();
+        settings
+            .percent_bad_hash(100.0)
+            .percent_bad_signature(100.0);
+
+        // Only one thing is wrong with each op.
+        let mut bad_hash = op.clone();
+        byzantine(&settings, Vec::new()).corrupt_op(&mut bad_hash);
+        assert_ne!(entry_mut(&mut bad_hash).cloned(), entry);
+        assert_eq!(*signature_mut(&mut bad_hash), Signature([1; 64]));
+
+        settings.percent_bad_hash(0.0);
+        let mut bad_signature = op.clone();
+        byzantine(&settings, Vec::new()).corrupt_op(&mut bad_signature);
+        assert_eq!(entry_mut(&mut bad_signature).cloned(), entry);
+        assert_ne!(*signature_mut(&mut bad_signature), Signature([1; 64]));
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::{Duration, SystemTime};
+
+use derive_builder::Builder;
+use holochain_keystore::MetaLairClient;
+use holochain_p2p::AgentPubKeyExt;
+use holochain_types::prelude::AgentPubKey;
+use kitsune_p2p::agent_store::AgentInfoSigned;
+
+use crate::agent_info::renew_agent_info;
+use crate::keys::{SeededKey, SeededKeys};
+
+use super::schedule::chance;
+use super::MockNetwork;
+
+/// Simulated agents coming online and going offline.
+///
+/// Each [`step`](Churn::step) some online agents leave and some
+/// offline agents come back.
+/// Agents that leave stop answering and their agent info is left to expire.
+/// Agents that come back, or whose info is close to expiring,
+/// are signed again with new times.
+pub struct Churn {
+    keystore: MetaLairClient,
+    agents: Vec<ChurnAgent>,
+    settings: ChurnSettings,
+    rng: fastrand::Rng,
+}
+
+#[derive(Builder, Clone)]
+pub struct ChurnSettings {
+    /// Percent chance each step that an online agent goes offline.
+    #[builder(default = "10.0")]
+    pub percent_leave: f32,
+    /// Percent chance each step that an offline agent comes back.
+    #[builder(default = "10.0")]
+    pub percent_join: f32,
+    /// How long renewed agent info lasts.
+    #[builder(default = "Duration::from_secs(60)")]
+    pub info_lifetime: Duration,
+    /// Seed for choosing who leaves and joins.
+    #[builder(default)]
+    pub seed: Option<u64>,
+    /// Keys for agents that aren't in the keystore.
+    #[builder(default)]
+    pub seeded: SeededKeys,
+}
+
+struct ChurnAgent {
+    agent: AgentPubKey,
+    key: Option<SeededKey>,
+    info: AgentInfoSigned,
+    online: bool,
+}
+
+impl Churn {
+    /// Churn these agents.
+    /// They all start online.
+    pub fn new(
+        keystore: MetaLairClient,
+        peer_data: Vec<AgentInfoSigned>,
+        settings: ChurnSettingsBuilder,
+    ) -> Self {
+        let settings = settings.build().unwrap();
+        let agents = peer_data
+            .into_iter()
+            .map(|info| {
+                let agent = AgentPubKey::from_kitsune(&info.agent);
+                ChurnAgent {
+                    key: settings.seeded.get(&agent).copied(),
+                    agent,
+                    info,
+                    online: true,
+                }
+            })
+            .collect();
+        Self {
+            keystore,
+            agents,
+            rng: settings
+                .seed
+                .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            settings,
+        }
+    }
+
+    /// Move agents on and offline and renew agent info.
+    /// Returns the renewed agent info which should be given to the conductor.
+    pub async fn step(&mut self, network: &mut MockNetwork) -> Vec<AgentInfoSigned> {
+        let now = SystemTime::now();
+        let renew_before = now + self.settings.info_lifetime / 2;
+        let mut renewed = Vec::new();
+        for churn in &mut self.agents {
+            if churn.online {
+                if chance(&self.rng, self.settings.percent_leave) {
+                    churn.online = false;
+                    network.set_online(&churn.agent, false);
+                    continue;
+                }
+                if expires_at(&churn.info) > renew_before {
+                    continue;
+                }
+            } else if chance(&self.rng, self.settings.percent_join) {
+                churn.online = true;
+                network.set_online(&churn.agent, true);
+            } else {
+                continue;
+            }
+            churn.info = renew_agent_info(
+                &self.keystore,
+                &churn.info,
+                churn.key.as_ref(),
+                now,
+                now + self.settings.info_lifetime,
+            )
+            .await;
+            renewed.push(churn.info.clone());
+        }
+        renewed
+    }
+
+    pub fn online(&self) -> impl Iterator<Item = &AgentPubKey> {
+        self.agents.iter().filter(|a| a.online).map(|a| &a.agent)
+    }
+
+    pub fn offline(&self) -> impl Iterator<Item = &AgentPubKey> {
+        self.agents.iter().filter(|a| !a.online).map(|a| &a.agent)
+    }
+}
+
+fn expires_at(info: &AgentInfoSigned) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(info.expires_at_ms)
+}
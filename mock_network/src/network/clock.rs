@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// Where the mock network gets its time from.
+#[derive(Clone)]
+pub enum Clock {
+    /// Real time.
+    Wall,
+    /// Time only moves when the clock is advanced.
+    Virtual(VirtualClock),
+}
+
+/// A clock that is moved forward by hand.
+/// Advancing it wakes sleepers in deadline order, earliest first.
+/// Sleepers with the same deadline wake in the order they started sleeping.
+#[derive(Clone)]
+pub struct VirtualClock {
+    start: Instant,
+    inner: Arc<Mutex<Sleepers>>,
+}
+
+#[derive(Default)]
+struct Sleepers {
+    elapsed: Duration,
+    next_id: u64,
+    /// Sorted by deadline then by when they started sleeping.
+    waiting: BTreeMap<(Duration, u64), oneshot::Sender<()>>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::Wall
+    }
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::Wall => Instant::now(),
+            Clock::Virtual(clock) => clock.now(),
+        }
+    }
+
+    pub async fn sleep_until(&self, at: Instant) {
+        match self {
+            Clock::Wall => tokio::time::sleep_until(at).await,
+            Clock::Virtual(clock) => clock.sleep_until(at).await,
+        }
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration).await
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            inner: Default::default(),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    /// How far the clock has been advanced.
+    pub fn elapsed(&self) -> Duration {
+        self.inner.lock().unwrap().elapsed
+    }
+
+    /// Move the clock forward, stopping at each deadline on the way
+    /// to wake whatever is sleeping until it.
+    ///
+    /// After each wake this yields so the woken task can run while the
+    /// clock still reads its deadline, like `tokio::time::advance`.
+    /// On a multi-threaded runtime the woken task may run later
+    /// and see a later time.
+    pub async fn advance(&self, by: Duration) {
+        let to = self.elapsed() + by;
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                let (deadline, id) = match inner.waiting.keys().next() {
+                    Some(&(deadline, id)) if deadline <= to => (deadline, id),
+                    _ => {
+                        inner.elapsed = to;
+                        return;
+                    }
+                };
+                let wake = inner.waiting.remove(&(deadline, id)).unwrap();
+                inner.elapsed = deadline;
+                // The sleeper may have been dropped.
+                let _ = wake.send(());
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn sleep_until(&self, at: Instant) {
+        let deadline = at.saturating_duration_since(self.start);
+        let (woken, id) = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.elapsed >= deadline {
+                return;
+            }
+            let (tx, rx) = oneshot::channel();
+            let id = inner.next_id;
+            inner.next_id += 1;
+            inner.waiting.insert((deadline, id), tx);
+            (rx, id)
+        };
+        // Stop waiting if the sleep is dropped before it wakes.
+        let _registered = Registered {
+            clock: self,
+            key: (deadline, id),
+        };
+        let _ = woken.await;
+    }
+}
+
+struct Registered<'a> {
+    clock: &'a VirtualClock,
+    key: (Duration, u64),
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        self.clock.inner.lock().unwrap().waiting.remove(&self.key);
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(woke: &Mutex<Vec<(usize, Duration)>>) -> Vec<(usize, u128)> {
+        let woke = woke.lock().unwrap();
+        woke.iter().map(|(i, at)| (*i, at.as_millis())).collect()
+    }
+
+    /// Each sleeper runs while the clock reads its own deadline.
+    #[tokio::test(flavor = "current_thread")]
+    async fn wakes_in_deadline_order() {
+        let clock = VirtualClock::new();
+        let woke = Arc::new(Mutex::new(Vec::new()));
+        let handles: Vec<_> = [30, 10, 20, 10]
+            .into_iter()
+            .enumerate()
+            .map(|(i, ms)| {
+                let clock = clock.clone();
+                let woke = woke.clone();
+                tokio::spawn(async move {
+                    clock
+                        .sleep_until(clock.now() + Duration::from_millis(ms))
+                        .await;
+                    woke.lock().unwrap().push((i, clock.elapsed()));
+                })
+            })
+            .collect();
+        // Let every sleeper register.
+        tokio::task::yield_now().await;
+        clock.advance(Duration::from_millis(15)).await;
+        assert_eq!(order(&woke), vec![(1, 10), (3, 10)]);
+        clock.advance(Duration::from_millis(15)).await;
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(order(&woke), vec![(1, 10), (3, 10), (2, 20), (0, 30)]);
+        assert_eq!(clock.elapsed(), Duration::from_millis(30));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn dropped_sleepers_are_forgotten() {
+        let clock = VirtualClock::new();
+        let mut sleep = Box::pin(clock.sleep_until(clock.now() + Duration::from_secs(1)));
+        assert!(futures::poll!(sleep.as_mut()).is_pending());
+        assert_eq!(clock.inner.lock().unwrap().waiting.len(), 1);
+        drop(sleep);
+        assert!(clock.inner.lock().unwrap().waiting.is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+use holochain_p2p::mock_network::*;
+use holochain_p2p::wire::WireMessage;
+use holochain_types::prelude::AgentPubKey;
+use kitsune_p2p::gossip::sharded_gossip::ShardedGossipWire;
+
+/// The kind of a mock network message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MsgKind {
+    CallRemote,
+    Publish,
+    ValidationReceipt,
+    Get,
+    GetMeta,
+    GetLinks,
+    GetAgentActivity,
+    /// Any other wire message.
+    OtherWire,
+    CallResp,
+    GossipInitiate,
+    GossipAccept,
+    GossipAgents,
+    GossipMissingAgents,
+    GossipOpBloom,
+    GossipMissingOps,
+    GossipOpBatchReceived,
+    /// Any other sharded gossip message or simple bloom gossip.
+    OtherGossip,
+    Failure,
+    /// Peer discovery and metrics.
+    Other,
+}
+
+impl MsgKind {
+    /// Every kind of gossip message.
+    pub const GOSSIP: [MsgKind; 8] = [
+        Self::GossipInitiate,
+        Self::GossipAccept,
+        Self::GossipAgents,
+        Self::GossipMissingAgents,
+        Self::GossipOpBloom,
+        Self::GossipMissingOps,
+        Self::GossipOpBatchReceived,
+        Self::OtherGossip,
+    ];
+
+    pub fn of(msg: &HolochainP2pMockMsg) -> Self {
+        match msg {
+            HolochainP2pMockMsg::Wire { msg, .. } => match msg {
+                WireMessage::CallRemote { .. } => Self::CallRemote,
+                WireMessage::Publish { .. } => Self::Publish,
+                WireMessage::ValidationReceipt { .. } => Self::ValidationReceipt,
+                WireMessage::Get { .. } => Self::Get,
+                WireMessage::GetMeta { .. } => Self::GetMeta,
+                WireMessage::GetLinks { .. } => Self::GetLinks,
+                WireMessage::GetAgentActivity { .. } => Self::GetAgentActivity,
+                _ => Self::OtherWire,
+            },
+            HolochainP2pMockMsg::CallResp(_) => Self::CallResp,
+            HolochainP2pMockMsg::Gossip {
+                gossip: GossipProtocol::Sharded(gossip),
+                ..
+            } => match gossip {
+                ShardedGossipWire::Initiate(_) => Self::GossipInitiate,
+                ShardedGossipWire::Accept(_) => Self::GossipAccept,
+                ShardedGossipWire::Agents(_) => Self::GossipAgents,
+                ShardedGossipWire::MissingAgents(_) => Self::GossipMissingAgents,
+                ShardedGossipWire::OpBloom(_) => Self::GossipOpBloom,
+                ShardedGossipWire::MissingOps(_) => Self::GossipMissingOps,
+                ShardedGossipWire::OpBatchReceived(_) => Self::GossipOpBatchReceived,
+                _ => Self::OtherGossip,
+            },
+            HolochainP2pMockMsg::Gossip { .. } => Self::OtherGossip,
+            HolochainP2pMockMsg::Failure(_) => Self::Failure,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn is_gossip(&self) -> bool {
+        Self::GOSSIP.contains(self)
+    }
+}
+
+impl fmt::Display for MsgKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Which messages from the real conductor to keep.
+/// An empty filter matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct MsgFilter {
+    kinds: HashSet<MsgKind>,
+    to: HashSet<AgentPubKey>,
+    from: HashSet<AgentPubKey>,
+}
+
+impl MsgFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match messages of this kind.
+    /// Can be called more than once to match any of the kinds.
+    pub fn kind(mut self, kind: MsgKind) -> Self {
+        self.kinds.insert(kind);
+        self
+    }
+
+    /// Match any gossip message.
+    pub fn gossip(self) -> Self {
+        MsgKind::GOSSIP.into_iter().fold(self, Self::kind)
+    }
+
+    /// Match messages to this simulated agent.
+    pub fn to_agent(mut self, agent: AgentPubKey) -> Self {
+        self.to.insert(agent);
+        self
+    }
+
+    /// Match messages from this real agent.
+    /// Only wire messages say which real agent they are from.
+    pub fn from_agent(mut self, agent: AgentPubKey) -> Self {
+        self.from.insert(agent);
+        self
+    }
+
+    pub fn matches(&self, msg: &AddressedHolochainP2pMockMsg) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&MsgKind::of(&msg.msg)) {
+            return false;
+        }
+        if !self.to.is_empty() && !self.to.contains(&msg.agent) {
+            return false;
+        }
+        if !self.from.is_empty() {
+            let from = match &msg.msg {
+                HolochainP2pMockMsg::Wire { from_agent, .. } => from_agent,
+                _ => return false,
+            };
+            if !self.from.contains(from) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl From<MsgKind> for MsgFilter {
+    fn from(kind: MsgKind) -> Self {
+        Self::new().kind(kind)
+    }
+}
+
+impl fmt::Display for MsgFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kinds: Vec<_> = self.kinds.iter().collect();
+        kinds.sort();
+        if kinds.is_empty() {
+            write!(f, "any message")?;
+        } else {
+            write!(f, "{:?}", kinds)?;
+        }
+        if !self.to.is_empty() {
+            write!(f, " to {:?}", self.to)?;
+        }
+        if !self.from.is_empty() {
+            write!(f, " from {:?}", self.from)?;
+        }
+        Ok(())
+    }
+}
+
+/// An expected message didn't arrive.
+#[derive(Debug)]
+pub enum ExpectError {
+    /// Nothing matched before the timeout.
+    Timeout { filter: MsgFilter, after: Duration },
+    /// The channel closed before anything matched.
+    Closed { filter: MsgFilter },
+}
+
+impl fmt::Display for ExpectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout { filter, after } => {
+                write!(
+                    f,
+                    "Expected {} but nothing arrived within {:?}",
+                    filter, after
+                )
+            }
+            Self::Closed { filter } => {
+                write!(f, "Expected {} but the network closed", filter)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExpectError {}
+
+#[cfg(test)]
+mod tests {
+    use holochain_types::prelude::SerializedBytes;
+
+    use super::*;
+    use crate::test_utils::{agent, wire};
+
+    fn call_resp(to: u8) -> AddressedHolochainP2pMockMsg {
+        AddressedHolochainP2pMockMsg {
+            agent: agent(to),
+            msg: HolochainP2pMockMsg::CallResp(SerializedBytes::try_from(()).unwrap()),
+        }
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = MsgFilter::new();
+        assert!(filter.matches(&wire(1, 2)));
+        assert!(filter.matches(&call_resp(1)));
+    }
+
+    #[test]
+    fn kinds_match_any_of_them() {
+        let filter = MsgFilter::new()
+            .kind(MsgKind::ValidationReceipt)
+            .kind(MsgKind::Get);
+        assert!(filter.matches(&wire(1, 2)));
+        assert!(!filter.matches(&call_resp(1)));
+        assert!(MsgFilter::from(MsgKind::CallResp).matches(&call_resp(1)));
+    }
+
+    #[test]
+    fn agents_narrow_the_match() {
+        let filter = MsgFilter::new().to_agent(agent(1));
+        assert!(filter.matches(&wire(1, 2)));
+        assert!(!filter.matches(&wire(3, 2)));
+        let filter = MsgFilter::new().from_agent(agent(2));
+        assert!(filter.matches(&wire(1, 2)));
+        assert!(!filter.matches(&wire(1, 3)));
+        // Only wire messages say who they are from.
+        assert!(!filter.matches(&call_resp(1)));
+    }
+
+    #[test]
+    fn gossip_filter_matches_every_gossip_kind() {
+        let filter = MsgFilter::new().gossip();
+        for kind in MsgKind::GOSSIP {
+            assert!(kind.is_gossip());
+            assert!(filter.kinds.contains(&kind));
+        }
+        assert!(!filter.matches(&call_resp(1)));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use holochain_p2p::mock_network::*;
+use holochain_p2p::{AgentPubKeyExt, DhtOpHashExt, WireDhtOpData};
+use holochain_types::dht_op::produce_ops_from_element;
+use holochain_types::prelude::{AgentPubKey, DhtOp, DhtOpHashed, DnaHash, Element, Timestamp};
+use kitsune_p2p::agent_store::AgentInfoSigned;
+use kitsune_p2p::gossip::sharded_gossip::{
+    decode_bloom_filter, EncodedTimedBloomFilter, MissingOpsStatus, ShardedGossipWire,
+};
+use kitsune_p2p::gossip::simple_bloom::MetaOpKey;
+use kitsune_p2p::KitsuneOpData;
+use kitsune_p2p_types::dht_arc::{ArcInterval, DhtLocation};
+use kitsune_p2p_types::KOpData;
+
+use super::responder::{Reply, Responder};
+
+/// Roughly how many bytes of ops go in a single missing ops message.
+const BATCH_BYTES: usize = 1_000_000;
+
+/// Answers sharded gossip from the real conductor as if each simulated
+/// agent held every generated op inside its arc.
+///
+/// Only the ops in the requested time window that aren't in the
+/// conductor's op bloom are sent.
+/// Simulated agents never ask for the conductor's ops.
+///
+/// Simple bloom gossip isn't answered at all,
+/// so runs using the simple bloom strategy get no ops from the responder.
+pub struct GossipResponder {
+    dna_hash: DnaHash,
+    agents: HashMap<AgentPubKey, AgentInfoSigned>,
+    /// Every op sorted by authored time.
+    ops: Vec<HeldOp>,
+    /// The intervals the conductor is gossiping over with each agent.
+    rounds: HashMap<AgentPubKey, Vec<ArcInterval>>,
+}
+
+struct HeldOp {
+    /// How the op appears in a bloom.
+    key: MetaOpKey,
+    loc: DhtLocation,
+    timestamp: Timestamp,
+    data: KOpData,
+}
+
+impl GossipResponder {
+    /// Serve the generated chains from the simulated agents in `peer_data`.
+    pub fn new(
+        dna_hash: DnaHash,
+        data: &HashMap<AgentPubKey, Vec<Element>>,
+        peer_data: &[AgentInfoSigned],
+    ) -> Self {
+        let mut ops: Vec<_> = data
+            .values()
+            .flatten()
+            .flat_map(|element| produce_ops_from_element(element).unwrap())
+            .map(|op| {
+                let op = DhtOpHashed::from_content_sync(op);
+                let (op, hash) = op.into_inner();
+                HeldOp {
+                    key: MetaOpKey::Op(hash.into_kitsune()),
+                    loc: op.dht_basis().get_loc(),
+                    timestamp: op.header().timestamp(),
+                    data: encode_op(op),
+                }
+            })
+            .collect();
+        ops.sort_by_key(|op| op.timestamp);
+        let agents = peer_data
+            .iter()
+            .map(|info| (AgentPubKey::from_kitsune(&info.agent), info.clone()))
+            .collect();
+        Self {
+            dna_hash,
+            agents,
+            ops,
+            rounds: HashMap::new(),
+        }
+    }
+
+    fn gossip(&mut self, agent: &AgentPubKey, wire: &ShardedGossipWire) -> Vec<ShardedGossipWire> {
+        let info = &self.agents[agent];
+        match wire {
+            ShardedGossipWire::Initiate(initiate) => {
+                self.rounds
+                    .insert(agent.clone(), initiate.intervals.clone());
+                vec![
+                    ShardedGossipWire::accept(
+                        vec![info.storage_arc.interval()],
+                        vec![info.clone()],
+                    ),
+                    ShardedGossipWire::op_bloom(EncodedTimedBloomFilter::NoOverlap, true),
+                ]
+            }
+            ShardedGossipWire::Agents(_) => vec![ShardedGossipWire::missing_agents(Vec::new())],
+            ShardedGossipWire::OpBloom(bloom) => {
+                let ops = match &bloom.missing_hashes {
+                    EncodedTimedBloomFilter::NoOverlap => Vec::new(),
+                    EncodedTimedBloomFilter::MissingAllHashes { time_window } => {
+                        self.ops_for(agent, time_window.clone(), |_| true)
+                    }
+                    EncodedTimedBloomFilter::HaveHashes {
+                        filter,
+                        time_window,
+                    } => {
+                        let have = decode_bloom_filter(filter);
+                        self.ops_for(agent, time_window.clone(), |key| !have.check(key))
+                    }
+                };
+                missing_ops(ops, bloom.finished)
+            }
+            ShardedGossipWire::MissingOps(missing)
+                if missing.finished == MissingOpsStatus::BatchComplete as u8 =>
+            {
+                vec![ShardedGossipWire::op_batch_received()]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The ops this agent holds that the conductor is gossiping over
+    /// and is missing.
+    fn ops_for(
+        &self,
+        agent: &AgentPubKey,
+        window: Range<Timestamp>,
+        missing: impl Fn(&MetaOpKey) -> bool,
+    ) -> Vec<KOpData> {
+        let arc = &self.agents[agent].storage_arc;
+        let intervals = match self.rounds.get(agent) {
+            Some(intervals) => intervals,
+            None => return Vec::new(),
+        };
+        let start = self.ops.partition_point(|op| op.timestamp < window.start);
+        self.ops[start..]
+            .iter()
+            .take_while(|op| op.timestamp < window.end)
+            .filter(|op| arc.contains(op.loc) && intervals.iter().any(|i| i.contains(op.loc)))
+            .filter(|op| missing(&op.key))
+            .map(|op| op.data.clone())
+            .collect()
+    }
+}
+
+/// Encode an op the way it is gossiped.
+pub(crate) fn encode_op(op: DhtOp) -> KOpData {
+    Arc::new(KitsuneOpData::new(
+        WireDhtOpData { op_data: op }.encode().unwrap(),
+    ))
+}
+
+/// Split the ops into messages.
+/// Only the last message of the last bloom ends the round.
+fn missing_ops(ops: Vec<KOpData>, finished: bool) -> Vec<ShardedGossipWire> {
+    let mut batches = vec![Vec::new()];
+    let mut bytes = 0;
+    for op in ops {
+        if bytes > BATCH_BYTES {
+            batches.push(Vec::new());
+            bytes = 0;
+        }
+        bytes += op.size();
+        batches.last_mut().unwrap().push(op);
+    }
+    let last = batches.len() - 1;
+    batches
+        .into_iter()
+        .enumerate()
+        .map(|(i, ops)| {
+            let status = if finished && i == last {
+                MissingOpsStatus::AllComplete
+            } else {
+                MissingOpsStatus::ChunkComplete
+            };
+            ShardedGossipWire::missing_ops(ops, status as u8)
+        })
+        .collect()
+}
+
+impl Responder for GossipResponder {
+    fn handle<'a>(
+        &'a mut self,
+        msg: &'a AddressedHolochainP2pMockMsg,
+    ) -> BoxFuture<'a, Option<Reply>> {
+        async move {
+            let (dna, module, wire) = match &msg.msg {
+                HolochainP2pMockMsg::Gossip {
+                    dna,
+                    module,
+                    gossip: GossipProtocol::Sharded(wire),
+                } => (dna, module, wire),
+                _ => return None,
+            };
+            if *dna != self.dna_hash || !self.agents.contains_key(&msg.agent) {
+                return None;
+            }
+            let send = self
+                .gossip(&msg.agent, wire)
+                .into_iter()
+                .map(|wire| AddressedHolochainP2pMockMsg {
+                    agent: msg.agent.clone(),
+                    msg: HolochainP2pMockMsg::Gossip {
+                        dna: dna.clone(),
+                        module: module.clone(),
+                        gossip: GossipProtocol::Sharded(wire),
+                    },
+                })
+                .collect();
+            Some(Reply::send(send))
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use holochain_p2p::mock_network::AddressedHolochainP2pMockMsg;
+
+use super::stats::Flow;
+
+/// Sees every message on its way through the network and decides what
+/// happens to it.
+///
+/// Messages from the conductor are intercepted after the network conditions
+/// are applied and before they reach a responder or the caller.
+/// Messages to the conductor, including responses, are intercepted
+/// before the network conditions are applied.
+/// Interceptors run in the order they were added.
+pub trait Interceptor: Send {
+    fn intercept(&mut self, cx: &mut Intercept<'_>, msg: AddressedHolochainP2pMockMsg) -> Action;
+}
+
+/// What happens to an intercepted message.
+pub enum Action {
+    /// Pass the message on, possibly rewritten.
+    Pass(AddressedHolochainP2pMockMsg),
+    /// Pass the message on after this long.
+    Delay(AddressedHolochainP2pMockMsg, Duration),
+    /// The message is lost.
+    Drop,
+}
+
+/// The message being intercepted.
+pub struct Intercept<'a> {
+    pub flow: Flow,
+    /// The message is a response to a request from the conductor.
+    pub response: bool,
+    inject: &'a mut Vec<AddressedHolochainP2pMockMsg>,
+}
+
+impl Intercept<'_> {
+    /// Send a new message to the conductor.
+    /// Injected messages don't go through the interceptors or the network conditions.
+    pub fn inject(&mut self, msg: AddressedHolochainP2pMockMsg) {
+        self.inject.push(msg);
+    }
+}
+
+impl<F> Interceptor for F
+where
+    F: FnMut(&mut Intercept<'_>, AddressedHolochainP2pMockMsg) -> Action + Send,
+{
+    fn intercept(&mut self, cx: &mut Intercept<'_>, msg: AddressedHolochainP2pMockMsg) -> Action {
+        self(cx, msg)
+    }
+}
+
+/// The interceptors shared with responses that are sent after the network has moved on.
+#[derive(Clone, Default)]
+pub(crate) struct Chain(Arc<Mutex<ChainInner>>);
+
+#[derive(Default)]
+struct ChainInner {
+    interceptors: Vec<Box<dyn Interceptor>>,
+    injected: Vec<AddressedHolochainP2pMockMsg>,
+}
+
+impl Chain {
+    pub(crate) fn push(&self, interceptor: Box<dyn Interceptor>) {
+        self.0.lock().unwrap().interceptors.push(interceptor);
+    }
+
+    /// Run the message through every interceptor.
+    /// Returns the message and how long to hold it or `None` if it was dropped.
+    pub(crate) fn run(
+        &self,
+        flow: Flow,
+        response: bool,
+        mut msg: AddressedHolochainP2pMockMsg,
+    ) -> Option<(AddressedHolochainP2pMockMsg, Duration)> {
+        let mut inner = self.0.lock().unwrap();
+        let ChainInner {
+            interceptors,
+            injected,
+        } = &mut *inner;
+        let mut delay = Duration::ZERO;
+        for interceptor in interceptors {
+            let mut cx = Intercept {
+                flow,
+                response,
+                inject: injected,
+            };
+            match interceptor.intercept(&mut cx, msg) {
+                Action::Pass(m) => msg = m,
+                Action::Delay(m, d) => {
+                    msg = m;
+                    delay += d;
+                }
+                Action::Drop => return None,
+            }
+        }
+        Some((msg, delay))
+    }
+
+    /// Messages interceptors want sent to the conductor.
+    pub(crate) fn take_injected(&self) -> Vec<AddressedHolochainP2pMockMsg> {
+        std::mem::take(&mut self.0.lock().unwrap().injected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{agent, wire};
+
+    #[test]
+    fn interceptors_run_in_order_on_the_rewritten_message() {
+        let chain = Chain::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..2 {
+            let seen = seen.clone();
+            chain.push(Box::new(
+                move |_: &mut Intercept<'_>, mut msg: AddressedHolochainP2pMockMsg| {
+                    seen.lock().unwrap().push((i, msg.agent.clone()));
+                    msg.agent = agent(i + 2);
+                    Action::Pass(msg)
+                },
+            ));
+        }
+        let (msg, delay) = chain.run(Flow::FromConductor, false, wire(1, 0)).unwrap();
+        assert_eq!(msg.agent, agent(3));
+        assert_eq!(delay, Duration::ZERO);
+        assert_eq!(*seen.lock().unwrap(), vec![(0, agent(1)), (1, agent(2))]);
+    }
+
+    #[test]
+    fn delays_add_up_and_drops_stop_the_chain() {
+        let chain = Chain::default();
+        for _ in 0..2 {
+            chain.push(Box::new(
+                |cx: &mut Intercept<'_>, msg: AddressedHolochainP2pMockMsg| {
+                    cx.inject(wire(0, 1));
+                    Action::Delay(msg, Duration::from_millis(10))
+                },
+            ));
+        }
+        let (_, delay) = chain.run(Flow::ToConductor, true, wire(1, 0)).unwrap();
+        assert_eq!(delay, Duration::from_millis(20));
+        assert_eq!(chain.take_injected().len(), 2);
+        assert!(chain.take_injected().is_empty());
+
+        let reached = Arc::new(Mutex::new(false));
+        chain.push(Box::new(
+            |cx: &mut Intercept<'_>, msg: AddressedHolochainP2pMockMsg| {
+                if cx.response {
+                    Action::Drop
+                } else {
+                    Action::Pass(msg)
+                }
+            },
+        ));
+        {
+            let reached = reached.clone();
+            chain.push(Box::new(
+                move |_: &mut Intercept<'_>, msg: AddressedHolochainP2pMockMsg| {
+                    *reached.lock().unwrap() = true;
+                    Action::Pass(msg)
+                },
+            ));
+        }
+        assert!(chain.run(Flow::ToConductor, true, wire(1, 0)).is_none());
+        assert!(!*reached.lock().unwrap());
+        assert!(chain.run(Flow::ToConductor, false, wire(1, 0)).is_some());
+        assert!(*reached.lock().unwrap());
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// The connection to a single simulated agent.
+#[derive(Clone, Debug)]
+pub struct LinkProfile {
+    /// One way latency added to messages in both directions.
+    pub latency: Range<Duration>,
+    /// Percent of messages to the agent that are lost.
+    pub percent_loss: f32,
+    /// How fast the link can move data.
+    /// `None` uses the network wide limit.
+    pub bytes_per_second: Option<u64>,
+}
+
+impl LinkProfile {
+    /// A well connected full arc host.
+    pub fn fast() -> Self {
+        Self {
+            latency: Duration::from_millis(5)..Duration::from_millis(20),
+            percent_loss: 0.0,
+            bytes_per_second: None,
+        }
+    }
+
+    /// A slow, lossy mobile connection.
+    pub fn mobile() -> Self {
+        Self {
+            latency: Duration::from_millis(150)..Duration::from_millis(600),
+            percent_loss: 5.0,
+            bytes_per_second: Some(128 * 1024),
+        }
+    }
+}
+
+impl Default for LinkProfile {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO..Duration::ZERO,
+            percent_loss: 0.0,
+            bytes_per_second: None,
+        }
+    }
+}
+
+/// How long it takes to move this many bytes.
+fn transfer_time(bytes: usize, bytes_per_second: Option<u64>) -> Duration {
+    match bytes_per_second {
+        Some(bps) if bps > 0 => Duration::from_secs_f64(bytes as f64 / bps as f64),
+        _ => Duration::ZERO,
+    }
+}
+
+/// One direction of a bandwidth limited link.
+/// Messages queue behind each other so a large message
+/// holds up everything sent after it.
+#[derive(Clone, Default)]
+pub(crate) struct Pipe(Arc<Mutex<Option<Instant>>>);
+
+impl Pipe {
+    /// Reserve the pipe for this many bytes.
+    /// Returns how long until the last byte is through.
+    pub(crate) fn reserve(
+        &self,
+        now: Instant,
+        bytes: usize,
+        bytes_per_second: Option<u64>,
+    ) -> Duration {
+        let transfer = transfer_time(bytes, bytes_per_second);
+        if transfer.is_zero() {
+            return Duration::ZERO;
+        }
+        let mut free_at = self.0.lock().unwrap();
+        let start = free_at.map_or(now, |free_at| free_at.max(now));
+        let done = start + transfer;
+        *free_at = Some(done);
+        done - now
+    }
+}
+
+/// Both directions of the link to a simulated agent.
+#[derive(Clone, Default)]
+pub(crate) struct Pipes {
+    pub(crate) inbound: Pipe,
+    pub(crate) outbound: Pipe,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_pipe_adds_no_delay() {
+        let pipe = Pipe::default();
+        assert_eq!(
+            pipe.reserve(Instant::now(), 1_000_000, None),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn messages_queue_behind_each_other() {
+        let now = Instant::now();
+        let pipe = Pipe::default();
+        let bps = Some(1000);
+        assert_eq!(pipe.reserve(now, 1000, bps), Duration::from_secs(1));
+        assert_eq!(pipe.reserve(now, 500, bps), Duration::from_millis(1500));
+        // Half a second later there is still a second queued ahead.
+        let later = now + Duration::from_millis(500);
+        assert_eq!(pipe.reserve(later, 500, bps), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn idle_pipe_starts_from_now() {
+        let now = Instant::now();
+        let pipe = Pipe::default();
+        pipe.reserve(now, 100, Some(1000));
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            pipe.reserve(later, 100, Some(1000)),
+            Duration::from_millis(100)
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+use holochain_types::prelude::AgentPubKey;
+use tokio::time::Instant;
+
+/// Groups of agents that cannot reach each other.
+///
+/// The real conductor is placed in a group by adding its agents.
+/// Agents that are not in any group can reach everyone.
+///
+/// Gossip doesn't say which real agent it is for, so the network
+/// must know the conductor's agents before it can be partitioned.
+#[derive(Clone, Default)]
+pub struct Partition {
+    groups: Vec<HashSet<AgentPubKey>>,
+    heal_at: Option<Instant>,
+    heal_after: Option<Duration>,
+}
+
+impl Partition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a group of agents that can only reach each other.
+    pub fn group<I>(mut self, agents: I) -> Self
+    where
+        I: IntoIterator<Item = AgentPubKey>,
+    {
+        self.groups.push(agents.into_iter().collect());
+        self
+    }
+
+    /// Heal the partition at this time.
+    pub fn heal_at(mut self, at: Instant) -> Self {
+        self.heal_at = Some(at);
+        self
+    }
+
+    /// Heal the partition this long after it is applied to the network.
+    pub fn heal_after(mut self, after: Duration) -> Self {
+        self.heal_after = Some(after);
+        self
+    }
+
+    /// The partition has been applied to the network.
+    pub(crate) fn start(&mut self, now: Instant) {
+        if let Some(after) = self.heal_after.take() {
+            self.heal_at = Some(now + after);
+        }
+    }
+
+    /// When this partition heals, if ever.
+    pub fn heals_at(&self) -> Option<Instant> {
+        self.heal_at
+    }
+
+    pub fn is_healed(&self, now: Instant) -> bool {
+        self.heal_at.map_or(false, |at| now >= at)
+    }
+
+    fn group_of(&self, agent: &AgentPubKey) -> Option<usize> {
+        self.groups.iter().position(|g| g.contains(agent))
+    }
+
+    /// Can these two agents reach each other while the partition holds?
+    pub fn can_reach(&self, a: &AgentPubKey, b: &AgentPubKey) -> bool {
+        match (self.group_of(a), self.group_of(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+}
+
+/// The network was partitioned before it knew which agents are the real conductor's.
+#[derive(Debug)]
+pub struct NoLocalAgents;
+
+impl fmt::Display for NoLocalAgents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Can't partition the network until the real conductor's agents are added"
+        )
+    }
+}
+
+impl std::error::Error for NoLocalAgents {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::agent;
+
+    #[test]
+    fn groups_only_reach_themselves() {
+        let partition = Partition::new()
+            .group([agent(0), agent(1)])
+            .group([agent(2)]);
+        assert!(partition.can_reach(&agent(0), &agent(1)));
+        assert!(!partition.can_reach(&agent(0), &agent(2)));
+        assert!(!partition.can_reach(&agent(2), &agent(1)));
+        // Agents outside every group reach everyone.
+        assert!(partition.can_reach(&agent(3), &agent(0)));
+        assert!(partition.can_reach(&agent(2), &agent(3)));
+    }
+
+    #[test]
+    fn heals_after_it_starts() {
+        let now = Instant::now();
+        let mut partition = Partition::new().heal_after(Duration::from_secs(5));
+        assert_eq!(partition.heals_at(), None);
+        partition.start(now);
+        assert_eq!(partition.heals_at(), Some(now + Duration::from_secs(5)));
+        assert!(!partition.is_healed(now + Duration::from_secs(4)));
+        assert!(partition.is_healed(now + Duration::from_secs(5)));
+        assert!(!Partition::new().is_healed(now));
+    }
+}
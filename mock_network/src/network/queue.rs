@@ -0,0 +1,101 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use tokio::time::Instant;
+
+/// Items waiting to be delivered at a later time.
+/// Items with the same due time come out in the order they went in.
+pub(crate) struct DelayQueue<T> {
+    heap: BinaryHeap<Reverse<Delayed<T>>>,
+    seq: u64,
+}
+
+struct Delayed<T> {
+    due: Instant,
+    seq: u64,
+    item: T,
+}
+
+impl<T> DelayQueue<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, due: Instant, item: T) {
+        self.seq += 1;
+        self.heap.push(Reverse(Delayed {
+            due,
+            seq: self.seq,
+            item,
+        }));
+    }
+
+    /// When the next item is due.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.heap.peek().map(|d| d.0.due)
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        self.heap.pop().map(|d| d.0.item)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+impl<T> PartialEq for Delayed<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due && self.seq == other.seq
+    }
+}
+
+impl<T> Eq for Delayed<T> {}
+
+impl<T> PartialOrd for Delayed<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Delayed<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn pops_in_due_order() {
+        let now = Instant::now();
+        let mut queue = DelayQueue::new();
+        queue.push(now + Duration::from_millis(30), 3);
+        queue.push(now + Duration::from_millis(10), 1);
+        queue.push(now + Duration::from_millis(20), 2);
+        assert_eq!(queue.next_due(), Some(now + Duration::from_millis(10)));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn same_due_time_keeps_insert_order() {
+        let due = Instant::now();
+        let mut queue = DelayQueue::new();
+        for i in 0..10 {
+            queue.push(due, i);
+        }
+        let popped: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(popped, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,209 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use derive_builder::Builder;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use holochain_keystore::MetaLairClient;
+use holochain_p2p::mock_network::*;
+use holochain_p2p::wire::WireMessage;
+use holochain_state::prelude::{SignedValidationReceipt, ValidationReceipt};
+use holochain_types::prelude::*;
+
+use crate::keys::{self, SeededKey, SeededKeys};
+
+use super::responder::{Reply, Responder};
+use super::schedule::{chance, random_delay};
+
+/// Answers publishes from the real conductor the way authorities would,
+/// with a validation receipt for each op when the conductor asks for them.
+///
+/// Receipts are signed by the simulated agent
+/// so its key must be in the keystore or in the settings.
+pub struct ReceiptResponder {
+    keystore: MetaLairClient,
+    dna_hash: DnaHash,
+    settings: ReceiptSettings,
+    rng: fastrand::Rng,
+}
+
+#[derive(Builder, Clone)]
+pub struct ReceiptSettings {
+    /// Receipts are sent this long after the publish arrives.
+    #[builder(default = "Duration::from_millis(100)..Duration::from_millis(500)")]
+    pub delay: Range<Duration>,
+    /// Percent of ops that never get a receipt.
+    #[builder(default = "0.0")]
+    pub percent_missing: f32,
+    /// Percent of receipts that say the op was rejected.
+    #[builder(default = "0.0")]
+    pub percent_rejected: f32,
+    /// Seed for choosing which receipts fail.
+    #[builder(default)]
+    pub seed: Option<u64>,
+    /// Keys for simulated agents that aren't in the keystore.
+    #[builder(default)]
+    pub seeded: SeededKeys,
+}
+
+impl ReceiptResponder {
+    pub fn new(
+        keystore: MetaLairClient,
+        dna_hash: DnaHash,
+        settings: ReceiptSettingsBuilder,
+    ) -> Self {
+        let settings = settings.build().unwrap();
+        Self {
+            keystore,
+            dna_hash,
+            rng: settings
+                .seed
+                .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            settings,
+        }
+    }
+
+    /// What this authority decided about an op, if it sends a receipt at all.
+    fn outcome(&self) -> Option<ValidationStatus> {
+        if chance(&self.rng, self.settings.percent_missing) {
+            None
+        } else if chance(&self.rng, self.settings.percent_rejected) {
+            Some(ValidationStatus::Rejected)
+        } else {
+            Some(ValidationStatus::Valid)
+        }
+    }
+}
+
+async fn receipt(
+    keystore: &MetaLairClient,
+    key: Option<&SeededKey>,
+    validator: &AgentPubKey,
+    dht_op_hash: DhtOpHash,
+    validation_status: ValidationStatus,
+) -> WireMessage {
+    let receipt = ValidationReceipt {
+        dht_op_hash,
+        validation_status,
+        validator: validator.clone(),
+        when_integrated: Timestamp::now(),
+    };
+    // Signed here so agents with seeded keys can send receipts too.
+    let validator_signature = keys::sign(keystore, validator, key, receipt.clone()).await;
+    let receipt = SignedValidationReceipt {
+        receipt,
+        validator_signature,
+    };
+    WireMessage::ValidationReceipt {
+        receipt: SerializedBytes::try_from(receipt).unwrap(),
+    }
+}
+
+impl Responder for ReceiptResponder {
+    fn handle<'a>(
+        &'a mut self,
+        msg: &'a AddressedHolochainP2pMockMsg,
+    ) -> BoxFuture<'a, Option<Reply>> {
+        async move {
+            let (to_agent, from_agent, dna, request_validation_receipt, ops) = match &msg.msg {
+                HolochainP2pMockMsg::Wire {
+                    to_agent,
+                    from_agent,
+                    dna,
+                    msg:
+                        WireMessage::Publish {
+                            request_validation_receipt,
+                            ops,
+                            ..
+                        },
+                } => (to_agent, from_agent, dna, request_validation_receipt, ops),
+                _ => return None,
+            };
+            if *dna != self.dna_hash {
+                return None;
+            }
+            // Decide everything up front because the rng can't be held across awaits.
+            let outcomes: Vec<_> = if *request_validation_receipt {
+                ops.iter()
+                    .filter_map(|(op_hash, _)| Some((op_hash.clone(), self.outcome()?)))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let delay = random_delay(&self.rng, &self.settings.delay);
+            let key = self.settings.seeded.get(to_agent);
+            let mut send = Vec::new();
+            for (op_hash, status) in outcomes {
+                send.push(AddressedHolochainP2pMockMsg {
+                    agent: msg.agent.clone(),
+                    msg: HolochainP2pMockMsg::Wire {
+                        to_agent: from_agent.clone(),
+                        from_agent: to_agent.clone(),
+                        dna: dna.clone(),
+                        msg: receipt(&self.keystore, key, to_agent, op_hash, status).await,
+                    },
+                });
+            }
+            // Acknowledge the publish if the conductor is waiting on it.
+            let ack = HolochainP2pMockMsg::CallResp(SerializedBytes::try_from(()).unwrap());
+            Some(Reply {
+                respond: Some(ack),
+                send,
+                delay,
+            })
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn responder(settings: &ReceiptSettingsBuilder) -> ReceiptResponder {
+        let keystore = holochain_keystore::test_keystore::spawn_test_keystore()
+            .await
+            .unwrap();
+        ReceiptResponder::new(
+            keystore,
+            DnaHash::from_raw_32(vec![0; 32]),
+            settings.clone(),
+        )
+    }
+
+    fn outcomes(responder: &ReceiptResponder) -> Vec<Option<ValidationStatus>> {
+        (0..100).map(|_| responder.outcome()).collect()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn same_seed_makes_the_same_outcomes() {
+        let mut settings = ReceiptSettingsBuilder::default();
+        settings
+            .percent_missing(30.0)
+            .percent_rejected(30.0)
+            .seed(Some(5));
+        let first = outcomes(&responder(&settings).await);
+        assert_eq!(first, outcomes(&responder(&settings).await));
+        assert!(first.contains(&None));
+        assert!(first.contains(&Some(ValidationStatus::Rejected)));
+        assert!(first.contains(&Some(ValidationStatus::Valid)));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn missing_is_decided_before_rejected() {
+        let mut settings = ReceiptSettingsBuilder::default();
+        settings.percent_missing(100.0).percent_rejected(100.0);
+        let all_missing = outcomes(&responder(&settings).await);
+        assert!(all_missing.iter().all(Option::is_none));
+        settings.percent_missing(0.0);
+        let all_rejected = outcomes(&responder(&settings).await);
+        assert!(all_rejected
+            .iter()
+            .all(|o| *o == Some(ValidationStatus::Rejected)));
+        settings.percent_rejected(0.0);
+        let all_valid = outcomes(&responder(&settings).await);
+        assert!(all_valid
+            .iter()
+            .all(|o| *o == Some(ValidationStatus::Valid)));
+    }
+}
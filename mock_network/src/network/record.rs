@@ -0,0 +1,434 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem::{discriminant, Discriminant};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use holochain_p2p::mock_network::*;
+use holochain_p2p::wire::WireMessage;
+use holochain_types::prelude::{AgentPubKey, DnaHash, SerializedBytes};
+use kitsune_p2p::gossip::sharded_gossip::ShardedGossipWire;
+use kitsune_p2p::gossip::simple_bloom::SimpleBloomWire;
+use kitsune_p2p::GossipModuleType;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use super::clock::Clock;
+use super::responder::{Reply, Responder};
+
+/// Start of every recording.
+const MAGIC: &[u8; 8] = b"MOCKREC\0";
+/// Bumped whenever [`Record`] changes shape.
+pub const RECORDING_VERSION: u32 = 2;
+
+/// A single message that went through the mock network.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Unique within a recording.
+    pub id: u64,
+    /// Time since the recording started.
+    pub at: Duration,
+    /// The simulated agent on the other side of the message.
+    pub agent: AgentPubKey,
+    pub direction: Direction,
+    pub msg: RecordedMsg,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Direction {
+    /// Sent by the real conductor to a simulated agent.
+    FromConductor,
+    /// Sent by a simulated agent to the real conductor.
+    ToConductor,
+    /// The answer to the request with this id.
+    Response { request: u64 },
+}
+
+/// The parts of a [`HolochainP2pMockMsg`] that can be replayed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecordedMsg {
+    Wire {
+        to_agent: AgentPubKey,
+        from_agent: AgentPubKey,
+        dna: DnaHash,
+        msg: WireMessage,
+    },
+    CallResp(SerializedBytes),
+    Gossip {
+        dna: DnaHash,
+        module: GossipModuleType,
+        gossip: ShardedGossipWire,
+    },
+    SimpleGossip {
+        dna: DnaHash,
+        module: GossipModuleType,
+        gossip: SimpleBloomWire,
+    },
+    Failure(String),
+    /// Anything else is kept for reading but not replayed.
+    Other(String),
+}
+
+impl From<&HolochainP2pMockMsg> for RecordedMsg {
+    fn from(msg: &HolochainP2pMockMsg) -> Self {
+        match msg.clone() {
+            HolochainP2pMockMsg::Wire {
+                to_agent,
+                from_agent,
+                dna,
+                msg,
+            } => Self::Wire {
+                to_agent,
+                from_agent,
+                dna,
+                msg,
+            },
+            HolochainP2pMockMsg::CallResp(resp) => Self::CallResp(resp),
+            HolochainP2pMockMsg::Gossip {
+                dna,
+                module,
+                gossip: GossipProtocol::Sharded(gossip),
+            } => Self::Gossip {
+                dna,
+                module,
+                gossip,
+            },
+            HolochainP2pMockMsg::Gossip {
+                dna,
+                module,
+                gossip: GossipProtocol::Simple(gossip),
+            } => Self::SimpleGossip {
+                dna,
+                module,
+                gossip,
+            },
+            HolochainP2pMockMsg::Failure(reason) => Self::Failure(reason),
+            msg => Self::Other(format!("{:?}", msg)),
+        }
+    }
+}
+
+impl RecordedMsg {
+    /// The message to put back on the network.
+    pub fn to_msg(&self) -> Option<HolochainP2pMockMsg> {
+        let msg = match self.clone() {
+            Self::Wire {
+                to_agent,
+                from_agent,
+                dna,
+                msg,
+            } => HolochainP2pMockMsg::Wire {
+                to_agent,
+                from_agent,
+                dna,
+                msg,
+            },
+            Self::CallResp(resp) => HolochainP2pMockMsg::CallResp(resp),
+            Self::Gossip {
+                dna,
+                module,
+                gossip,
+            } => HolochainP2pMockMsg::Gossip {
+                dna,
+                module,
+                gossip: GossipProtocol::Sharded(gossip),
+            },
+            Self::SimpleGossip {
+                dna,
+                module,
+                gossip,
+            } => HolochainP2pMockMsg::Gossip {
+                dna,
+                module,
+                gossip: GossipProtocol::Simple(gossip),
+            },
+            Self::Failure(reason) => HolochainP2pMockMsg::Failure(reason),
+            Self::Other(_) => return None,
+        };
+        Some(msg)
+    }
+}
+
+/// Writes records to a file.
+///
+/// The file starts with [`MAGIC`] and the version as a little endian `u32`.
+/// Each record is a little endian `u32` length followed by the record
+/// encoded as message pack.
+#[derive(Clone)]
+pub(crate) struct Recorder(Arc<Mutex<RecorderInner>>);
+
+struct RecorderInner {
+    out: BufWriter<File>,
+    start: Instant,
+    clock: Clock,
+    next_id: u64,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: impl AsRef<Path>, clock: Clock) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&RECORDING_VERSION.to_le_bytes())?;
+        Ok(Self(Arc::new(Mutex::new(RecorderInner {
+            out,
+            start: clock.now(),
+            clock,
+            next_id: 0,
+        }))))
+    }
+
+    /// Record a message and return its id.
+    pub(crate) fn record(
+        &self,
+        agent: &AgentPubKey,
+        direction: Direction,
+        msg: &HolochainP2pMockMsg,
+    ) -> u64 {
+        let mut inner = self.0.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let record = Record {
+            id,
+            at: inner.clock.now().saturating_duration_since(inner.start),
+            agent: agent.clone(),
+            direction,
+            msg: msg.into(),
+        };
+        let bytes = rmp_serde::to_vec_named(&record).expect("Failed to encode record");
+        let out = &mut inner.out;
+        let written = out
+            .write_all(&(bytes.len() as u32).to_le_bytes())
+            .and_then(|_| out.write_all(&bytes));
+        if let Err(e) = written {
+            holochain::tracing::error!(?e, "Failed to write record");
+        }
+        id
+    }
+
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        self.0.lock().unwrap().out.flush()
+    }
+}
+
+/// Read every record from a recording.
+pub fn read_recording(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a mock network recording",
+        ));
+    }
+    let mut word = [0; 4];
+    input.read_exact(&mut word)?;
+    let version = u32::from_le_bytes(word);
+    if version != RECORDING_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Recording is version {} but only version {} can be read",
+                version, RECORDING_VERSION
+            ),
+        ));
+    }
+    let mut records = Vec::new();
+    loop {
+        match input.read_exact(&mut word) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+            Err(e) => return Err(e),
+        }
+        let mut bytes = vec![0; u32::from_le_bytes(word) as usize];
+        input.read_exact(&mut bytes)?;
+        let record = rmp_serde::from_slice(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        records.push(record);
+    }
+}
+
+/// Plays a recording back to a real conductor.
+///
+/// Messages the simulated agents sent are sent again at the same times.
+/// Requests from the conductor are answered with the recorded response to
+/// the same kind of request to the same agent, in the order they were recorded.
+/// Requests with no recorded response are passed on to the caller.
+pub struct Replay {
+    sends: Vec<(Duration, AddressedHolochainP2pMockMsg)>,
+    responder: ReplayResponder,
+}
+
+type RequestKey = (AgentPubKey, Discriminant<WireMessage>);
+
+/// Answers requests from a recording.
+pub struct ReplayResponder {
+    responses: HashMap<RequestKey, VecDeque<HolochainP2pMockMsg>>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(read_recording(path)?))
+    }
+
+    pub fn new(records: Vec<Record>) -> Self {
+        let mut requests = HashMap::new();
+        let mut sends = Vec::new();
+        let mut responses: HashMap<_, VecDeque<_>> = HashMap::new();
+        for record in records {
+            let msg = match record.msg.to_msg() {
+                Some(msg) => msg,
+                None => continue,
+            };
+            match record.direction {
+                Direction::FromConductor => {
+                    if let Some(key) = request_key(&record.agent, &msg) {
+                        requests.insert(record.id, key);
+                    }
+                }
+                Direction::ToConductor => sends.push((
+                    record.at,
+                    AddressedHolochainP2pMockMsg {
+                        agent: record.agent,
+                        msg,
+                    },
+                )),
+                Direction::Response { request } => {
+                    if let Some(key) = requests.remove(&request) {
+                        responses.entry(key).or_default().push_back(msg);
+                    }
+                }
+            }
+        }
+        Self {
+            sends,
+            responder: ReplayResponder { responses },
+        }
+    }
+
+    /// The messages to send and when to send them after the replay starts.
+    pub(crate) fn into_parts(
+        self,
+    ) -> (
+        Vec<(Duration, AddressedHolochainP2pMockMsg)>,
+        ReplayResponder,
+    ) {
+        (self.sends, self.responder)
+    }
+}
+
+fn request_key(agent: &AgentPubKey, msg: &HolochainP2pMockMsg) -> Option<RequestKey> {
+    match msg {
+        HolochainP2pMockMsg::Wire { msg, .. } => Some((agent.clone(), discriminant(msg))),
+        _ => None,
+    }
+}
+
+impl Responder for ReplayResponder {
+    fn handle<'a>(
+        &'a mut self,
+        msg: &'a AddressedHolochainP2pMockMsg,
+    ) -> BoxFuture<'a, Option<Reply>> {
+        async move {
+            let key = request_key(&msg.agent, &msg.msg)?;
+            let response = self.responses.get_mut(&key)?.pop_front()?;
+            Some(Reply::respond(response))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{agent, wire};
+
+    fn request(to: u8) -> HolochainP2pMockMsg {
+        wire(to, 0).msg
+    }
+
+    fn resp(byte: u8) -> HolochainP2pMockMsg {
+        HolochainP2pMockMsg::CallResp(SerializedBytes::from(
+            holochain_types::prelude::UnsafeBytes::from(vec![byte]),
+        ))
+    }
+
+    #[test]
+    fn recording_round_trips() {
+        let path = std::env::temp_dir().join(format!("mock-record-{}", std::process::id()));
+        let recorder = Recorder::create(&path, Clock::Wall).unwrap();
+        let id = recorder.record(&agent(1), Direction::FromConductor, &request(1));
+        recorder.record(&agent(1), Direction::Response { request: id }, &resp(1));
+        recorder.record(&agent(2), Direction::ToConductor, &resp(2));
+        recorder.record(
+            &agent(2),
+            Direction::FromConductor,
+            &HolochainP2pMockMsg::Failure("lost".into()),
+        );
+        recorder.flush().unwrap();
+        let records = read_recording(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert!(matches!(
+            records[1].direction,
+            Direction::Response { request: 0 }
+        ));
+        assert_eq!(records[2].agent, agent(2));
+        assert!(matches!(&records[3].msg, RecordedMsg::Failure(r) if r == "lost"));
+        assert!(records.windows(2).all(|w| w[0].at <= w[1].at));
+    }
+
+    #[test]
+    fn reading_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("mock-not-record-{}", std::process::id()));
+        std::fs::write(&path, b"not a recording").unwrap();
+        let err = read_recording(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn replay_answers_with_recorded_responses() {
+        let record = |id, agent_i, direction, msg: HolochainP2pMockMsg| Record {
+            id,
+            at: Duration::ZERO,
+            agent: agent(agent_i),
+            direction,
+            msg: (&msg).into(),
+        };
+        let replay = Replay::new(vec![
+            record(0, 1, Direction::FromConductor, request(1)),
+            record(1, 1, Direction::Response { request: 0 }, resp(1)),
+            record(2, 2, Direction::ToConductor, resp(2)),
+        ]);
+        let (sends, mut responder) = replay.into_parts();
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].1.agent, agent(2));
+
+        let request = AddressedHolochainP2pMockMsg {
+            agent: agent(1),
+            msg: request(1),
+        };
+        let reply = responder.handle(&request).await.unwrap();
+        assert!(matches!(
+            reply.respond,
+            Some(HolochainP2pMockMsg::CallResp(_))
+        ));
+        // Each recorded response is only used once.
+        assert!(responder.handle(&request).await.is_none());
+        let other = AddressedHolochainP2pMockMsg {
+            agent: agent(2),
+            msg: request(2),
+        };
+        assert!(responder.handle(&other).await.is_none());
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use holochain_p2p::mock_network::{AddressedHolochainP2pMockMsg, HolochainP2pMockMsg};
+
+/// Answers messages from the real conductor on behalf of simulated agents.
+///
+/// Responders added to a [`MockNetwork`](super::MockNetwork) see each message
+/// before it is returned from [`next`](super::MockNetwork::next).
+/// A message that a responder handles is not returned.
+pub trait Responder: Send {
+    /// Handle a message or return `None` to pass it on.
+    fn handle<'a>(
+        &'a mut self,
+        msg: &'a AddressedHolochainP2pMockMsg,
+    ) -> BoxFuture<'a, Option<Reply>>;
+}
+
+/// What a responder does with a message it handled.
+#[derive(Default)]
+pub struct Reply {
+    /// The response if the message was a request.
+    pub respond: Option<HolochainP2pMockMsg>,
+    /// Messages to send to the real conductor.
+    pub send: Vec<AddressedHolochainP2pMockMsg>,
+    /// How long to wait before sending.
+    pub delay: Duration,
+}
+
+impl Reply {
+    /// Handled with nothing to say.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn respond(msg: HolochainP2pMockMsg) -> Self {
+        Self {
+            respond: Some(msg),
+            ..Default::default()
+        }
+    }
+
+    pub fn send(send: Vec<AddressedHolochainP2pMockMsg>) -> Self {
+        Self {
+            send,
+            ..Default::default()
+        }
+    }
+
+    /// Send after waiting this long on top of the network conditions.
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use derive_builder::Builder;
+use holochain_p2p::mock_network::AddressedHolochainP2pMockMsg;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+
+use super::{MockNetwork, MockRespond, Stats};
+
+/// Sends messages from simulated agents to the real conductor
+/// without owning the [`MockNetwork`].
+///
+/// Messages go out through the network conditions
+/// while [`next`](MockNetwork::next) is being polled
+/// or a [`Runtime`] is running.
+#[derive(Clone)]
+pub struct MockSender(mpsc::UnboundedSender<(AddressedHolochainP2pMockMsg, Duration)>);
+
+/// Handles messages from the conductor concurrently.
+///
+/// Each message is given to its own task.
+/// When every task is busy no more messages are taken from the conductor,
+/// so a slow handler fills the buffer instead of queueing without bound.
+pub struct Runtime {
+    network: MockNetwork,
+    settings: RuntimeSettings,
+}
+
+#[derive(Builder, Clone)]
+pub struct RuntimeSettings {
+    /// How many messages can be handled at once.
+    #[builder(default = "16")]
+    pub parallelism: usize,
+    /// How often to check the buffer while every handler is busy.
+    #[builder(default = "Duration::from_millis(100)")]
+    pub sample_interval: Duration,
+}
+
+/// What happened during a [`Runtime::run`].
+#[derive(Clone, Debug)]
+pub struct RuntimeReport {
+    pub handled: u64,
+    /// The most handlers that were running at once.
+    pub max_in_flight: usize,
+    /// How many times every handler was busy when a message could have been taken.
+    pub saturated: u64,
+    pub stats: Stats,
+}
+
+impl MockSender {
+    pub(crate) fn channel() -> (
+        Self,
+        mpsc::UnboundedReceiver<(AddressedHolochainP2pMockMsg, Duration)>,
+    ) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self(tx), rx)
+    }
+
+    pub fn send(&self, msg: AddressedHolochainP2pMockMsg) {
+        self.send_after(msg, Duration::ZERO);
+    }
+
+    /// Send after waiting this long on top of the network conditions.
+    pub fn send_after(&self, msg: AddressedHolochainP2pMockMsg, after: Duration) {
+        // The network owns the receiver so this only fails once it is gone.
+        let _ = self.0.send((msg, after));
+    }
+}
+
+impl Runtime {
+    pub fn new(network: MockNetwork, settings: RuntimeSettingsBuilder) -> Self {
+        Self {
+            network,
+            settings: settings.build().unwrap(),
+        }
+    }
+
+    /// The network for adding responders, interceptors and conditions before running.
+    pub fn network(&mut self) -> &mut MockNetwork {
+        &mut self.network
+    }
+
+    /// Handle every message until the conductor closes the channel
+    /// and the handlers still running have finished.
+    ///
+    /// Responders on the network still run first, one message at a time.
+    /// Messages the handlers send and delayed messages keep going out
+    /// while every handler is busy, and any still waiting when the
+    /// channel closes are sent before this returns.
+    pub async fn run<F, Fut>(mut self, mut handler: F) -> RuntimeReport
+    where
+        F: FnMut(AddressedHolochainP2pMockMsg, Option<MockRespond>, MockSender) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let parallelism = self.settings.parallelism;
+        let semaphore = Arc::new(Semaphore::new(parallelism));
+        let mut report = RuntimeReport {
+            handled: 0,
+            max_in_flight: 0,
+            saturated: 0,
+            stats: Stats::default(),
+        };
+        loop {
+            let permit = match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    report.saturated += 1;
+                    self.wait_for_permit(&semaphore).await
+                }
+            };
+            let (msg, respond) = match self.network.next().await {
+                Some(msg) => msg,
+                None => break,
+            };
+            report.handled += 1;
+            report.max_in_flight = report
+                .max_in_flight
+                .max(parallelism - semaphore.available_permits());
+            let handling = handler(msg, respond, self.network.sender());
+            tokio::spawn(async move {
+                handling.await;
+                drop(permit);
+            });
+        }
+        let _ = semaphore.acquire_many(parallelism as u32).await;
+        self.network.drain_sends().await;
+        report.stats = self.network.stats();
+        report
+    }
+
+    /// Wait for a handler to finish, watching the buffer fill up meanwhile.
+    /// Sends from the handlers keep going out while waiting.
+    async fn wait_for_permit(&mut self, semaphore: &Arc<Semaphore>) -> OwnedSemaphorePermit {
+        let acquire = semaphore.clone().acquire_owned();
+        tokio::pin!(acquire);
+        let mut interval = tokio::time::interval(self.settings.sample_interval);
+        loop {
+            tokio::select! {
+                permit = &mut acquire => return permit.expect("The semaphore is never closed"),
+                _ = interval.tick() => (),
+                _ = self.network.pump_sends() => (),
+            }
+            self.network.sample_buffer();
+        }
+    }
+}
+
+impl fmt::Display for RuntimeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "handled: {}, max in flight: {}, saturated: {}",
+            self.handled, self.max_in_flight, self.saturated
+        )?;
+        write!(f, "{}", self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use holochain::conductor::config::ConductorConfig;
+    use holochain::conductor::ConductorBuilder;
+    use holochain::sweettest::*;
+    use holochain_p2p::mock_network::HolochainP2pMockMsg;
+    use holochain_types::prelude::*;
+
+    use super::*;
+    use crate::agent_info::GenerateAgentInfo;
+
+    /// Calls `ping` on a simulated agent, which never answers successfully.
+    fn zome() -> InlineZome {
+        InlineZome::new_unique(vec![]).callback("call_ping", |api, to: AgentPubKey| {
+            let call = CallRemote::new(
+                to,
+                "zome1".into(),
+                "ping".into(),
+                None,
+                ExternIO::encode(())?,
+            );
+            let _ = api.call_remote(vec![call]);
+            Ok(())
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn handlers_never_exceed_the_parallelism() {
+        let (dna_file, _) = SweetDnaFile::from_inline_zome("".into(), "zome1", zome())
+            .await
+            .unwrap();
+        let keystore = holochain_keystore::test_keystore::spawn_test_keystore()
+            .await
+            .unwrap();
+        let mut simulated = Vec::new();
+        for _ in 0..3 {
+            simulated.push(keystore.new_sign_keypair_random().await.unwrap());
+        }
+        let agent_info = GenerateAgentInfo {
+            keystore: &keystore,
+            agent_keys: simulated.iter(),
+            dna_hash: dna_file.dna_hash().clone(),
+            settings: Default::default(),
+        }
+        .make()
+        .await;
+
+        let (network, config) = crate::setup(agent_info.clone());
+        let config = ConductorConfig {
+            network: Some(config),
+            ..Default::default()
+        };
+        let builder = ConductorBuilder::new()
+            .config(config)
+            .with_keystore(keystore.clone());
+        let mut conductor = SweetConductor::from_builder(builder).await;
+        conductor.add_agent_infos(agent_info).await.unwrap();
+        let app = conductor
+            .setup_app("app", &[dna_file.clone()])
+            .await
+            .unwrap();
+        let (cell,) = app.into_tuple();
+        let zome = cell.zome("zome1");
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let runtime = Runtime::new(
+            network,
+            RuntimeSettingsBuilder::default().parallelism(2).clone(),
+        );
+        let running = {
+            let in_flight = in_flight.clone();
+            let most = most.clone();
+            tokio::spawn(runtime.run(move |_msg, respond, _sender| {
+                let in_flight = in_flight.clone();
+                let most = most.clone();
+                async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    if let Some(respond) = respond {
+                        respond.respond(HolochainP2pMockMsg::Failure("busy".into()));
+                    }
+                }
+            }))
+        };
+
+        // More calls at once than there are handlers.
+        let calls = simulated.iter().cycle().take(8).map(|to| {
+            let (conductor, zome) = (&conductor, &zome);
+            async move {
+                let _: () = conductor.call(zome, "call_ping", to.clone()).await;
+            }
+        });
+        futures::future::join_all(calls).await;
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        running.abort();
+    }
+}
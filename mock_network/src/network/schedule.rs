@@ -0,0 +1,148 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use holochain_p2p::mock_network::MockScenario;
+
+/// Network conditions that change over time.
+///
+/// Phases run one after another starting from when the schedule
+/// is given to the [`MockNetwork`](crate::MockNetwork).
+/// Once the last phase ends no extra conditions are applied.
+/// These conditions are applied on top of the scenario
+/// the mock channel was created with.
+#[derive(Clone, Default)]
+pub struct Schedule {
+    phases: Vec<Phase>,
+}
+
+#[derive(Clone)]
+pub struct Phase {
+    pub duration: Duration,
+    pub scenario: MockScenario,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply this scenario for the duration.
+    pub fn then(mut self, duration: Duration, scenario: MockScenario) -> Self {
+        self.phases.push(Phase { duration, scenario });
+        self
+    }
+
+    /// Don't add any conditions for the duration.
+    pub fn then_clean(self, duration: Duration) -> Self {
+        self.then(duration, clean())
+    }
+
+    /// Drop this percent of messages for the duration.
+    pub fn then_drop(self, duration: Duration, percent_drop_msg: f32) -> Self {
+        self.then(
+            duration,
+            MockScenario {
+                percent_drop_msg,
+                ..clean()
+            },
+        )
+    }
+
+    /// Delay messages in both directions for the duration.
+    pub fn then_delay(self, duration: Duration, delay_range: Range<Duration>) -> Self {
+        self.then(
+            duration,
+            MockScenario {
+                inbound_delay_range: delay_range.clone(),
+                outbound_delay_range: delay_range,
+                ..clean()
+            },
+        )
+    }
+
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.phases.is_empty()
+    }
+
+    /// The phase index and scenario that is active after
+    /// the schedule has been running for this long.
+    pub fn at(&self, elapsed: Duration) -> Option<(usize, &MockScenario)> {
+        let mut end = Duration::ZERO;
+        for (i, phase) in self.phases.iter().enumerate() {
+            end += phase.duration;
+            if elapsed < end {
+                return Some((i, &phase.scenario));
+            }
+        }
+        None
+    }
+}
+
+/// A scenario with no drops and no delays.
+pub fn clean() -> MockScenario {
+    MockScenario {
+        percent_drop_msg: 0.0,
+        percent_offline: 0.0,
+        inbound_delay_range: Duration::ZERO..Duration::ZERO,
+        outbound_delay_range: Duration::ZERO..Duration::ZERO,
+    }
+}
+
+/// Choose a random duration from the range.
+pub(crate) fn random_delay(rng: &fastrand::Rng, range: &Range<Duration>) -> Duration {
+    if range.end <= range.start {
+        return range.start;
+    }
+    let spread = (range.end - range.start).as_nanos() as u64;
+    range.start + Duration::from_nanos(rng.u64(0..spread))
+}
+
+/// Returns true with the given percent chance.
+pub(crate) fn chance(rng: &fastrand::Rng, percent: f32) -> bool {
+    percent > 0.0 && rng.f32() * 100.0 < percent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn at_finds_the_active_phase() {
+        let schedule = Schedule::new()
+            .then_drop(secs(10), 50.0)
+            .then_clean(secs(5))
+            .then_delay(secs(1), secs(1)..secs(2));
+        let phase = |elapsed| schedule.at(elapsed).map(|(i, _)| i);
+        assert_eq!(phase(Duration::ZERO), Some(0));
+        assert_eq!(phase(secs(9)), Some(0));
+        assert_eq!(phase(secs(10)), Some(1));
+        assert_eq!(phase(secs(15)), Some(2));
+        assert_eq!(phase(secs(16)), None);
+        assert_eq!(schedule.at(secs(3)).unwrap().1.percent_drop_msg, 50.0);
+    }
+
+    #[test]
+    fn empty_schedule_has_no_phase() {
+        assert!(Schedule::new().at(Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn random_delay_stays_in_range() {
+        let rng = fastrand::Rng::with_seed(1);
+        let range = secs(1)..secs(2);
+        for _ in 0..100 {
+            assert!(range.contains(&random_delay(&rng, &range)));
+        }
+        assert_eq!(random_delay(&rng, &(secs(3)..secs(3))), secs(3));
+        let tiny = secs(3)..secs(3) + Duration::from_nanos(1);
+        assert_eq!(random_delay(&rng, &tiny), secs(3));
+    }
+}
@@ -0,0 +1,21 @@
+use holochain_p2p::mock_network::{GossipProtocol, HolochainP2pMockMsg};
+use serde::Serialize;
+
+/// Roughly how many bytes this message takes on the wire.
+/// Peer discovery and metrics messages are tiny so they count as zero.
+pub(crate) fn msg_size(msg: &HolochainP2pMockMsg) -> usize {
+    match msg {
+        HolochainP2pMockMsg::Wire { msg, .. } => encoded_len(msg),
+        HolochainP2pMockMsg::CallResp(resp) => encoded_len(resp),
+        HolochainP2pMockMsg::Gossip { gossip, .. } => match gossip {
+            GossipProtocol::Sharded(gossip) => encoded_len(gossip),
+            GossipProtocol::Simple(gossip) => encoded_len(gossip),
+        },
+        HolochainP2pMockMsg::Failure(reason) => reason.len(),
+        _ => 0,
+    }
+}
+
+fn encoded_len<T: Serialize>(t: &T) -> usize {
+    rmp_serde::to_vec_named(t).map_or(0, |b| b.len())
+}
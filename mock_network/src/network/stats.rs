@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use holochain_types::prelude::AgentPubKey;
+
+use super::filter::MsgKind;
+
+/// Upper bounds of the latency histogram buckets in milliseconds.
+/// Anything slower goes in the last bucket.
+const LATENCY_BUCKETS_MS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/// Which way a message went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Flow {
+    /// From the real conductor to a simulated agent.
+    FromConductor,
+    /// From a simulated agent to the real conductor, including responses.
+    ToConductor,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Count {
+    pub msgs: u64,
+    pub bytes: u64,
+}
+
+/// Running totals for everything that went through a [`MockNetwork`](super::MockNetwork).
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub by_kind: BTreeMap<(MsgKind, Flow), Count>,
+    pub by_agent: HashMap<(AgentPubKey, Flow), Count>,
+    pub by_flow: BTreeMap<Flow, Count>,
+    /// Messages lost to offline agents, partitions and the network conditions.
+    /// These aren't in the other counts.
+    pub dropped: BTreeMap<Flow, Count>,
+    /// Time from a request arriving from the conductor to the
+    /// response being sent back, including simulated delays.
+    pub response_latency: Histogram,
+    pub buffer: BufferStats,
+}
+
+/// How full the buffer of messages from the conductor has been.
+/// Sampled each time a message is taken from it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BufferStats {
+    pub capacity: usize,
+    pub used: usize,
+    pub max_used: usize,
+    /// How many times the buffer filled up and blocked the conductor.
+    pub times_full: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    total: Duration,
+    max: Duration,
+}
+
+impl Count {
+    fn add(&mut self, bytes: usize) {
+        self.msgs += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Stats {
+    pub(crate) fn count(&mut self, agent: &AgentPubKey, kind: MsgKind, flow: Flow, bytes: usize) {
+        self.by_kind.entry((kind, flow)).or_default().add(bytes);
+        self.by_agent
+            .entry((agent.clone(), flow))
+            .or_default()
+            .add(bytes);
+        self.by_flow.entry(flow).or_default().add(bytes);
+    }
+
+    pub(crate) fn dropped(&mut self, flow: Flow, bytes: usize) {
+        self.dropped.entry(flow).or_default().add(bytes);
+    }
+
+    /// Returns true if the buffer has just filled up.
+    pub(crate) fn buffer_used(&mut self, used: usize, capacity: usize) -> bool {
+        let was_full = self.buffer.is_full();
+        self.buffer.capacity = capacity;
+        self.buffer.used = used;
+        self.buffer.max_used = self.buffer.max_used.max(used);
+        let filled = self.buffer.is_full() && !was_full;
+        if filled {
+            self.buffer.times_full += 1;
+        }
+        filled
+    }
+
+    /// The agents with the most bytes going this way.
+    pub fn busiest_agents(&self, flow: Flow, n: usize) -> Vec<(&AgentPubKey, Count)> {
+        let mut agents: Vec<_> = self
+            .by_agent
+            .iter()
+            .filter(|((_, f), _)| *f == flow)
+            .map(|((a, _), c)| (a, *c))
+            .collect();
+        agents.sort_by_key(|(_, c)| std::cmp::Reverse(c.bytes));
+        agents.truncate(n);
+        agents
+    }
+}
+
+impl BufferStats {
+    /// The conductor can't send until a message is taken.
+    pub fn is_full(&self) -> bool {
+        self.capacity > 0 && self.used >= self.capacity
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&upper| ms <= upper)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        (count > 0).then(|| self.total / count as u32)
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Each bucket's upper bound and count.
+    /// The last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        LATENCY_BUCKETS_MS
+            .iter()
+            .map(|&ms| Some(Duration::from_millis(ms)))
+            .chain(std::iter::once(None))
+            .zip(self.counts.iter().copied())
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; LATENCY_BUCKETS_MS.len() + 1],
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<40} {:>10} {:>14}", "kind", "msgs", "bytes")?;
+        for ((kind, flow), count) in &self.by_kind {
+            let name = format!("{} {:?}", kind, flow);
+            writeln!(f, "{:<40} {:>10} {:>14}", name, count.msgs, count.bytes)?;
+        }
+        for (flow, count) in &self.by_flow {
+            let name = format!("total {:?}", flow);
+            writeln!(f, "{:<40} {:>10} {:>14}", name, count.msgs, count.bytes)?;
+        }
+        for (flow, count) in &self.dropped {
+            let name = format!("dropped {:?}", flow);
+            writeln!(f, "{:<40} {:>10} {:>14}", name, count.msgs, count.bytes)?;
+        }
+        for flow in [Flow::FromConductor, Flow::ToConductor] {
+            let agents = self.busiest_agents(flow, usize::MAX);
+            writeln!(f, "agents {:?}: {}", flow, agents.len())?;
+            for (agent, count) in agents.into_iter().take(5) {
+                writeln!(f, "  {} {:>10} {:>14}", agent, count.msgs, count.bytes)?;
+            }
+        }
+        write!(f, "{}", self.response_latency)?;
+        write!(
+            f,
+            "buffer: {}/{} used, max {}, full {} times",
+            self.buffer.used, self.buffer.capacity, self.buffer.max_used, self.buffer.times_full
+        )
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "response latency: {} responses, mean {:?}, max {:?}",
+            self.count(),
+            self.mean().unwrap_or_default(),
+            self.max
+        )?;
+        let mut last = Duration::ZERO;
+        for (upper, count) in self.buckets() {
+            match upper {
+                Some(upper) => {
+                    writeln!(f, "  <= {:>8?} {}", upper, count)?;
+                    last = upper;
+                }
+                None => writeln!(f, "   > {:>8?} {}", last, count)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stats shared with responses that are sent after the network has moved on.
+pub(crate) type SharedStats = Arc<Mutex<Stats>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn histogram_buckets_by_upper_bound() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.mean(), None);
+        for latency in [ms(1), ms(3), ms(5), ms(10_000)] {
+            histogram.record(latency);
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.max(), ms(10_000));
+        assert_eq!(
+            histogram.mean(),
+            Some(ms(2502) + Duration::from_micros(250))
+        );
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS_MS.len() + 1);
+        assert_eq!(buckets[0], (Some(ms(1)), 1));
+        assert_eq!(buckets[1], (Some(ms(2)), 0));
+        assert_eq!(buckets[2], (Some(ms(5)), 2));
+        assert_eq!(*buckets.last().unwrap(), (None, 1));
+    }
+
+    #[test]
+    fn agents_are_counted_per_direction() {
+        let a = AgentPubKey::from_raw_32(vec![1; 32]);
+        let b = AgentPubKey::from_raw_32(vec![2; 32]);
+        let mut stats = Stats::default();
+        stats.count(&a, MsgKind::Get, Flow::FromConductor, 100);
+        stats.count(&a, MsgKind::CallResp, Flow::ToConductor, 10);
+        stats.count(&b, MsgKind::Get, Flow::ToConductor, 50);
+        stats.dropped(Flow::FromConductor, 7);
+        let to = stats.busiest_agents(Flow::ToConductor, 5);
+        assert_eq!(to.len(), 2);
+        assert_eq!(to[0].0, &b);
+        assert_eq!(to[0].1.bytes, 50);
+        let from = stats.busiest_agents(Flow::FromConductor, 5);
+        assert_eq!(
+            from,
+            vec![(
+                &a,
+                Count {
+                    msgs: 1,
+                    bytes: 100
+                }
+            )]
+        );
+        assert_eq!(stats.by_flow[&Flow::FromConductor].bytes, 100);
+        assert_eq!(stats.dropped[&Flow::FromConductor].msgs, 1);
+    }
+
+    #[test]
+    fn buffer_full_is_reported_once() {
+        let mut stats = Stats::default();
+        assert!(!stats.buffer_used(5, 10));
+        assert!(stats.buffer_used(10, 10));
+        assert!(!stats.buffer_used(10, 10));
+        assert!(!stats.buffer_used(3, 10));
+        assert!(stats.buffer_used(10, 10));
+        assert_eq!(stats.buffer.times_full, 2);
+        assert_eq!(stats.buffer.max_used, 10);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use holochain_p2p::mock_network::*;
+use holochain_p2p::wire::WireMessage;
+use holochain_p2p::WireDhtOpData;
+use holochain_state::prelude::{DatabaseResult, DbKindDht, DbRead};
+use holochain_state::test_utils::{test_dht_db, TestDb};
+use holochain_types::prelude::*;
+use kitsune_p2p::gossip::sharded_gossip::ShardedGossipWire;
+use rusqlite::{params, Params};
+
+use crate::insert_op_as_authority;
+
+use super::intercept::{Action, Intercept, Interceptor};
+use super::stats::Flow;
+
+/// Stores every op the real conductor publishes or gossips to a simulated agent
+/// in a database for that agent.
+///
+/// Add a clone of this as an interceptor and keep the original for queries.
+/// The databases use the same schema as
+/// [`insert_element_as_authority`](crate::insert_element_as_authority)
+/// so ops that reached an agent can be found with ordinary queries.
+/// Ops lost to the network conditions never reach the store.
+///
+/// Only published ops and sharded gossip are read,
+/// so ops sent over simple bloom gossip aren't stored.
+#[derive(Clone)]
+pub struct SimulatedStore {
+    dna_hash: DnaHash,
+    dbs: Arc<Mutex<HashMap<AgentPubKey, TestDb<DbKindDht>>>>,
+}
+
+impl SimulatedStore {
+    pub fn new(dna_hash: DnaHash) -> Self {
+        Self {
+            dna_hash,
+            dbs: Default::default(),
+        }
+    }
+
+    /// The agents that have been sent at least one op.
+    pub fn agents(&self) -> Vec<AgentPubKey> {
+        self.dbs.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// The ops this agent has been sent.
+    pub fn db(&self, agent: &AgentPubKey) -> Option<DbRead<DbKindDht>> {
+        self.dbs
+            .lock()
+            .unwrap()
+            .get(agent)
+            .map(|db| db.to_db().into())
+    }
+
+    /// How many ops this agent has been sent.
+    pub fn op_count(&self, agent: &AgentPubKey) -> usize {
+        self.count(agent, "SELECT COUNT(*) FROM DhtOp", params![])
+    }
+
+    /// The agents that have been sent this op.
+    pub fn holders(&self, op_hash: &DhtOpHash) -> Vec<AgentPubKey> {
+        self.agents()
+            .into_iter()
+            .filter(|agent| {
+                self.count(
+                    agent,
+                    "SELECT COUNT(*) FROM DhtOp WHERE hash = ?",
+                    params![op_hash],
+                ) > 0
+            })
+            .collect()
+    }
+
+    fn count<P: Params>(&self, agent: &AgentPubKey, sql: &str, params: P) -> usize {
+        let db = match self.db(agent) {
+            Some(db) => db,
+            None => return 0,
+        };
+        let conn = db.conn().unwrap();
+        let count: i64 = conn.query_row(sql, params, |row| row.get(0)).unwrap();
+        count as usize
+    }
+
+    fn insert(&self, agent: &AgentPubKey, ops: impl Iterator<Item = DhtOp>) {
+        let mut dbs = self.dbs.lock().unwrap();
+        let db = dbs.entry(agent.clone()).or_insert_with(test_dht_db);
+        db.to_db()
+            .conn()
+            .unwrap()
+            .with_commit_sync(|txn| {
+                for op in ops {
+                    insert_op_as_authority(txn, &op);
+                }
+                DatabaseResult::Ok(())
+            })
+            .unwrap();
+    }
+}
+
+/// The ops in a message from the conductor.
+/// Simple bloom gossip is skipped.
+fn ops_in(msg: &HolochainP2pMockMsg) -> Vec<DhtOp> {
+    match msg {
+        HolochainP2pMockMsg::Wire {
+            msg: WireMessage::Publish { ops, .. },
+            ..
+        } => ops.iter().map(|(_, op)| op.clone()).collect(),
+        HolochainP2pMockMsg::Gossip {
+            gossip: GossipProtocol::Sharded(ShardedGossipWire::MissingOps(missing)),
+            ..
+        } => missing
+            .ops
+            .iter()
+            .filter_map(|op| WireDhtOpData::decode(op.0.clone()).ok())
+            .map(|data| data.op_data)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl Interceptor for SimulatedStore {
+    fn intercept(&mut self, cx: &mut Intercept<'_>, msg: AddressedHolochainP2pMockMsg) -> Action {
+        let dna = match &msg.msg {
+            HolochainP2pMockMsg::Wire { dna, .. } | HolochainP2pMockMsg::Gossip { dna, .. } => dna,
+            _ => return Action::Pass(msg),
+        };
+        if cx.flow != Flow::FromConductor || *dna != self.dna_hash {
+            return Action::Pass(msg);
+        }
+        let ops = ops_in(&msg.msg);
+        if !ops.is_empty() {
+            self.insert(&msg.agent, ops.into_iter());
+        }
+        Action::Pass(msg)
+    }
+}
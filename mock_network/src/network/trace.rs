@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use holochain_types::prelude::AgentPubKey;
+use serde_json::{json, Value};
+
+use super::filter::MsgKind;
+use super::record::{Direction, Record};
+
+/// Turn a recording into the Chrome trace event format.
+///
+/// Each simulated agent is a process in the trace.
+/// Requests and their responses are spans, gossip rounds with an agent
+/// are spans from one initiate to the next and everything else is an instant.
+pub fn chrome_trace(records: &[Record]) -> Value {
+    let mut trace = Trace::default();
+    let requests: HashMap<u64, &Record> = records
+        .iter()
+        .filter(|r| matches!(r.direction, Direction::FromConductor))
+        .map(|r| (r.id, r))
+        .collect();
+    let answered: HashSet<u64> = records
+        .iter()
+        .filter_map(|r| match r.direction {
+            Direction::Response { request } => Some(request),
+            _ => None,
+        })
+        .collect();
+    // When the current gossip round with each agent started.
+    let mut rounds: HashMap<AgentPubKey, (u64, Duration)> = HashMap::new();
+    let mut last_gossip: HashMap<AgentPubKey, Duration> = HashMap::new();
+    for record in records {
+        let pid = trace.pid(&record.agent);
+        let kind = kind_of(record);
+        match &record.direction {
+            Direction::Response { request } => {
+                if let Some(request) = requests.get(request) {
+                    let name = kind_of(request).to_string();
+                    trace.span(pid, "request", &name, request.id, request.at, record.at);
+                }
+            }
+            Direction::FromConductor if answered.contains(&record.id) => (),
+            direction => trace.instant(pid, &kind.to_string(), direction, record.at),
+        }
+        if !kind.is_gossip() {
+            continue;
+        }
+        if kind == MsgKind::GossipInitiate {
+            if let Some((id, start)) = rounds.remove(&record.agent) {
+                let end = last_gossip.get(&record.agent).copied().unwrap_or(start);
+                trace.span(pid, "gossip", "gossip round", id, start, end);
+            }
+            rounds.insert(record.agent.clone(), (record.id, record.at));
+        }
+        last_gossip.insert(record.agent.clone(), record.at);
+    }
+    for (agent, (id, start)) in rounds {
+        let pid = trace.pid(&agent);
+        let end = last_gossip.get(&agent).copied().unwrap_or(start);
+        trace.span(pid, "gossip", "gossip round", id, start, end);
+    }
+    json!({ "traceEvents": trace.events, "displayTimeUnit": "ms" })
+}
+
+/// Write a recording as a Chrome trace that can be opened in a trace viewer.
+pub fn write_chrome_trace(records: &[Record], path: impl AsRef<Path>) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut out, &chrome_trace(records))?;
+    out.flush()
+}
+
+fn kind_of(record: &Record) -> MsgKind {
+    record
+        .msg
+        .to_msg()
+        .map_or(MsgKind::Other, |msg| MsgKind::of(&msg))
+}
+
+#[derive(Default)]
+struct Trace {
+    events: Vec<Value>,
+    pids: HashMap<AgentPubKey, usize>,
+}
+
+impl Trace {
+    /// The process for this agent.
+    fn pid(&mut self, agent: &AgentPubKey) -> usize {
+        if let Some(pid) = self.pids.get(agent) {
+            return *pid;
+        }
+        let pid = self.pids.len() + 1;
+        self.pids.insert(agent.clone(), pid);
+        self.events.push(json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": pid,
+            "args": { "name": agent.to_string() },
+        }));
+        pid
+    }
+
+    fn span(&mut self, pid: usize, cat: &str, name: &str, id: u64, start: Duration, end: Duration) {
+        for (ph, ts) in [("b", start), ("e", end)] {
+            self.events.push(json!({
+                "name": name,
+                "cat": cat,
+                "ph": ph,
+                "id": id,
+                "pid": pid,
+                "tid": 0,
+                "ts": ts.as_micros() as u64,
+            }));
+        }
+    }
+
+    fn instant(&mut self, pid: usize, name: &str, direction: &Direction, at: Duration) {
+        self.events.push(json!({
+            "name": name,
+            "cat": "message",
+            "ph": "i",
+            "s": "t",
+            "pid": pid,
+            "tid": 0,
+            "ts": at.as_micros() as u64,
+            "args": { "direction": format!("{:?}", direction) },
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use holochain_p2p::mock_network::HolochainP2pMockMsg;
+    use holochain_types::prelude::SerializedBytes;
+
+    use super::*;
+    use crate::test_utils::{agent, wire};
+
+    fn record(id: u64, ms: u64, to: u8, direction: Direction, msg: HolochainP2pMockMsg) -> Record {
+        Record {
+            id,
+            at: Duration::from_millis(ms),
+            agent: agent(to),
+            direction,
+            msg: (&msg).into(),
+        }
+    }
+
+    fn resp() -> HolochainP2pMockMsg {
+        HolochainP2pMockMsg::CallResp(SerializedBytes::try_from(()).unwrap())
+    }
+
+    fn events<'a>(trace: &'a Value, ph: &'a str) -> impl Iterator<Item = &'a Value> {
+        trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(move |e| e["ph"] == ph)
+    }
+
+    #[test]
+    fn requests_pair_with_their_responses() {
+        let trace = chrome_trace(&[
+            record(0, 10, 1, Direction::FromConductor, wire(1, 0).msg),
+            record(1, 20, 2, Direction::ToConductor, resp()),
+            record(2, 30, 1, Direction::Response { request: 0 }, resp()),
+            record(3, 50, 1, Direction::FromConductor, wire(1, 0).msg),
+        ]);
+        let begins: Vec<_> = events(&trace, "b").collect();
+        let ends: Vec<_> = events(&trace, "e").collect();
+        assert_eq!(begins.len(), 1);
+        assert_eq!(ends.len(), 1);
+        let name = MsgKind::ValidationReceipt.to_string();
+        for (event, ts) in [(begins[0], 10_000), (ends[0], 30_000)] {
+            assert_eq!(event["cat"], "request");
+            assert_eq!(event["name"], name.as_str());
+            assert_eq!(event["id"], 0);
+            assert_eq!(event["pid"], 1);
+            assert_eq!(event["ts"], ts);
+        }
+        // The other agent's message and the unanswered request are instants.
+        let instants: Vec<_> = events(&trace, "i").map(|e| (&e["pid"], &e["ts"])).collect();
+        assert_eq!(
+            instants,
+            vec![(&json!(2), &json!(20_000)), (&json!(1), &json!(50_000))]
+        );
+    }
+}
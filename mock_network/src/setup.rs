@@ -1,45 +1,152 @@
+use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::Duration;
 
+use derive_builder::Builder;
 use holochain_p2p::mock_network::{HolochainP2pMockChannel, MockScenario};
+use holochain_types::prelude::AgentPubKey;
 use kitsune_p2p::{agent_store::AgentInfoSigned, KitsuneP2pConfig, TransportConfig};
+use kitsune_p2p_types::config::tuning_params_struct::KitsuneP2pTuningParams;
 use kitsune_p2p_types::tx2::tx2_adapter::AdapterFactory;
 
 use super::*;
 
+/// Everything needed to describe a simulated network.
+/// The defaults match what [`setup`] has always used.
+#[derive(Builder, Clone)]
+pub struct NetworkSettings {
+    /// Percent of individual messages that are dropped.
+    #[builder(default = "0.0")]
+    pub percent_drop_msg: f32,
+    /// Percent of simulated agents that will never be online.
+    #[builder(default = "0.0")]
+    pub percent_offline: f32,
+    /// Simulated agents will receive messages within this range.
+    #[builder(default = "Duration::from_millis(50)..Duration::from_millis(150)")]
+    pub inbound_delay_range: Range<Duration>,
+    /// Simulated agents will send messages within this range.
+    #[builder(default = "Duration::from_millis(50)..Duration::from_millis(150)")]
+    pub outbound_delay_range: Range<Duration>,
+    /// How many network messages to buffer.
+    #[builder(default = "1000")]
+    pub buffer: usize,
+    /// The tuning params handed to the real conductor.
+    #[builder(default = "default_tuning()")]
+    pub tuning: KitsuneP2pTuningParams,
+    /// Conditions that change over time on top of the scenario above.
+    #[builder(default)]
+    pub schedule: Schedule,
+    /// Links for individual simulated agents on top of the scenario above.
+    #[builder(default)]
+    pub links: HashMap<AgentPubKey, LinkProfile>,
+    /// Bandwidth limit for every link that doesn't set its own.
+    #[builder(default)]
+    pub bytes_per_second: Option<u64>,
+    /// Percent of messages that are delivered twice.
+    #[builder(default = "0.0")]
+    pub percent_duplicate_msg: f32,
+    /// Percent of messages that are held back so later messages overtake them.
+    #[builder(default = "0.0")]
+    pub percent_reorder_msg: f32,
+    /// How long reordered messages are held back for
+    /// and how late duplicates arrive.
+    #[builder(default = "Duration::from_millis(500)")]
+    pub reorder_window: Duration,
+    /// Seed every random choice the mock network makes.
+    ///
+    /// The scenario above is then applied by the [`MockNetwork`] instead of the
+    /// channel so the same seed and the same messages give the same faults.
+    /// It is also applied there when the clock is virtual.
+    /// The real conductor's own timing is still not deterministic.
+    #[builder(default)]
+    pub seed: Option<u64>,
+    /// Where delays get their time from.
+    #[builder(default)]
+    pub clock: Clock,
+}
+
+impl NetworkSettings {
+    /// The scenario described by these settings.
+    pub fn scenario(&self) -> MockScenario {
+        MockScenario {
+            percent_drop_msg: self.percent_drop_msg,
+            percent_offline: self.percent_offline,
+            inbound_delay_range: self.inbound_delay_range.clone(),
+            outbound_delay_range: self.outbound_delay_range.clone(),
+        }
+    }
+
+    /// Whether the [`MockNetwork`] applies the scenario instead of the channel.
+    /// The channel only knows random wall clock delays.
+    pub(crate) fn mock_applies_scenario(&self) -> bool {
+        self.seed.is_some() || matches!(self.clock, Clock::Virtual(_))
+    }
+}
+
+impl NetworkSettingsBuilder {
+    /// Set the link for a single simulated agent.
+    pub fn link(&mut self, agent: AgentPubKey, link: LinkProfile) -> &mut Self {
+        self.links
+            .get_or_insert_with(HashMap::new)
+            .insert(agent, link);
+        self
+    }
+
+    /// The tuning params to change in place, starting from the default if unset.
+    pub fn tuning_mut(&mut self) -> &mut KitsuneP2pTuningParams {
+        self.tuning.get_or_insert_with(default_tuning)
+    }
+}
+
+/// Sharded gossip with dynamic arcs.
+pub fn default_tuning() -> KitsuneP2pTuningParams {
+    let mut tuning = KitsuneP2pTuningParams::default();
+    tuning.gossip_strategy = "sharded-gossip".to_string();
+    tuning.gossip_dynamic_arcs = true;
+    tuning
+}
+
 pub fn setup(peer_data: Vec<AgentInfoSigned>) -> (MockNetwork, KitsuneP2pConfig) {
+    setup_with(peer_data, NetworkSettingsBuilder::default())
+}
+
+pub fn setup_with(
+    peer_data: Vec<AgentInfoSigned>,
+    settings: NetworkSettingsBuilder,
+) -> (MockNetwork, KitsuneP2pConfig) {
+    let settings = settings.build().unwrap();
+    let scenario = if settings.mock_applies_scenario() {
+        clean()
+    } else {
+        settings.scenario()
+    };
+
     // Create the simulated network.
     let (from_kitsune_tx, to_kitsune_rx, channel) = HolochainP2pMockChannel::channel(
         // Pass in the generated simulated peer data.
         peer_data,
-        // We want to buffer up to 1000 network messages.
-        1000,
-        MockScenario {
-            // Don't drop any individual messages.
-            percent_drop_msg: 0.0,
-            // A random 10% of simulated agents will never be online.
-            percent_offline: 0.0,
-            // Simulated agents will receive messages from within 50 to 100 ms.
-            inbound_delay_range: std::time::Duration::from_millis(50)
-                ..std::time::Duration::from_millis(150),
-            // Simulated agents will send messages from within 50 to 100 ms.
-            outbound_delay_range: std::time::Duration::from_millis(50)
-                ..std::time::Duration::from_millis(150),
-        },
+        settings.buffer,
+        scenario,
     );
+    // The buffer is a bounded tokio channel, which is also what kitsune's
+    // mock transport takes, so it can be watched without keeping it open.
+    let buffer_tx: tokio::sync::mpsc::WeakSender<_> = from_kitsune_tx.downgrade();
     let mock_network =
         kitsune_p2p::test_util::mock_network::mock_network(from_kitsune_tx, to_kitsune_rx);
     let mock_network: AdapterFactory = Arc::new(mock_network);
 
     // Setup the network.
-    let mut tuning =
-        kitsune_p2p_types::config::tuning_params_struct::KitsuneP2pTuningParams::default();
-    tuning.gossip_strategy = "sharded-gossip".to_string();
-    tuning.gossip_dynamic_arcs = true;
-
     let mut network = KitsuneP2pConfig::default();
     network.transport_pool = vec![TransportConfig::Mock {
         mock_network: mock_network.into(),
     }];
-    network.tuning_params = Arc::new(tuning);
-    (MockNetwork::new(channel), network)
+    network.tuning_params = Arc::new(settings.tuning.clone());
+    let buffer = settings.buffer;
+    let mut mock = MockNetwork::new(channel, settings);
+    mock.set_buffer_probe(Box::new(move || match buffer_tx.upgrade() {
+        Some(tx) => (buffer - tx.capacity(), buffer),
+        None => (0, buffer),
+    }));
+    (mock, network)
 }
@@ -0,0 +1,24 @@
+//! Fixtures shared by the unit tests.
+use holochain_p2p::mock_network::{AddressedHolochainP2pMockMsg, HolochainP2pMockMsg};
+use holochain_p2p::wire::WireMessage;
+use holochain_types::prelude::{AgentPubKey, DnaHash, SerializedBytes};
+
+/// A made up agent that is the same for the same byte.
+pub(crate) fn agent(i: u8) -> AgentPubKey {
+    AgentPubKey::from_raw_32(vec![i; 32])
+}
+
+/// A validation receipt between two made up agents.
+pub(crate) fn wire(to: u8, from: u8) -> AddressedHolochainP2pMockMsg {
+    AddressedHolochainP2pMockMsg {
+        agent: agent(to),
+        msg: HolochainP2pMockMsg::Wire {
+            to_agent: agent(to),
+            from_agent: agent(from),
+            dna: DnaHash::from_raw_32(vec![0; 32]),
+            msg: WireMessage::ValidationReceipt {
+                receipt: SerializedBytes::try_from(()).unwrap(),
+            },
+        },
+    }
+}
@@ -5,11 +5,28 @@ use holochain_types::prelude::{
     builder::*, AppEntryBytes, Entry, EntryHash, EntryType, Header, LinkTag, ZomeId,
 };
 mod builder;
+mod links;
 pub use builder::*;
+pub use links::*;
 
 pub struct ChainData {
     pub header: ChainHeader,
     pub entry: Option<Entry>,
+    /// References that were filled with arbitrary hashes
+    /// and can be pointed at real elements instead.
+    pub unresolved: Unresolved,
+}
+
+/// Which references in a header were left unset by its builder.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unresolved {
+    /// The base of a link.
+    pub base: bool,
+    /// The target of a link.
+    pub target: bool,
+    /// The element an update or delete points at,
+    /// or the link a link delete removes.
+    pub original: bool,
 }
 
 pub enum ChainHeader {
@@ -66,11 +83,34 @@ impl From<ChainHeader> for ChainData {
                 Some(entry)
             }
         };
-        ChainData { header: h, entry }
+        ChainData {
+            header: h,
+            entry,
+            unresolved: Unresolved::default(),
+        }
     }
 }
 
 thread_local!(static DATA: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(1000)));
+thread_local!(static RNG: RefCell<fastrand::Rng> = RefCell::new(fastrand::Rng::new()));
+
+/// Make everything [`make`] generates on this thread from now on
+/// the same for the same seed.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| rng.borrow().seed(seed));
+    DATA.with(|d| d.borrow_mut().clear());
+}
+
+/// Make everything [`make`] generates inside `f` from this rng.
+/// The thread's own rng carries on afterwards as if `f` never ran.
+pub fn with_rng<T>(rng: &fastrand::Rng, f: impl FnOnce() -> T) -> T {
+    let own_rng = RNG.with(|r| r.replace(fastrand::Rng::with_seed(rng.u64(..))));
+    let own_data = DATA.with(|d| std::mem::take(&mut *d.borrow_mut()));
+    let t = f();
+    RNG.with(|r| r.replace(own_rng));
+    DATA.with(|d| *d.borrow_mut() = own_data);
+    t
+}
 
 pub fn make<T, F>(f: F) -> T
 where
@@ -99,9 +139,11 @@ where
         if needs_more_data {
             DATA.with(|d| {
                 // Add more data
-                let rng = fastrand::Rng::new();
-                d.borrow_mut()
-                    .extend(std::iter::repeat_with(|| rng.u8(..)).take(1000));
+                RNG.with(|rng| {
+                    let rng = rng.borrow();
+                    d.borrow_mut()
+                        .extend(std::iter::repeat_with(|| rng.u8(..)).take(1000));
+                });
             });
             needs_more_data = false;
         }
@@ -45,6 +45,57 @@ pub struct CreateBuilder {
     pub entry_hash: Option<EntryHash>,
 }
 
+/// The references a builder leaves unset.
+trait Refs {
+    fn unresolved(&self) -> Unresolved {
+        Unresolved::default()
+    }
+}
+
+impl Refs for CreateLinkBuilder {
+    fn unresolved(&self) -> Unresolved {
+        Unresolved {
+            base: self.base_address.is_none(),
+            target: self.target_address.is_none(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Refs for DeleteLinkBuilder {
+    fn unresolved(&self) -> Unresolved {
+        Unresolved {
+            original: self.link_add_address.is_none() && self.base_address.is_none(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Refs for DeleteBuilder {
+    fn unresolved(&self) -> Unresolved {
+        Unresolved {
+            original: self.deletes_address.is_none() && self.deletes_entry_address.is_none(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Refs for UpdateBuilder {
+    fn unresolved(&self) -> Unresolved {
+        Unresolved {
+            original: self.original_header_address.is_none()
+                && self.original_entry_address.is_none(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Refs for CloseChainBuilder {}
+
+impl Refs for OpenChainBuilder {}
+
+impl Refs for CreateBuilder {}
+
 macro_rules! make_from {
     ($t:ident, $v:ident, $( $i:ident ),+) => {
         impl From<$t> for ChainHeader {
@@ -57,7 +108,11 @@ macro_rules! make_from {
 
         impl From<$t> for ChainData {
             fn from(b: $t) -> Self {
-                ChainHeader::from(b).into()
+                let unresolved = b.unresolved();
+                ChainData {
+                    unresolved,
+                    ..ChainHeader::from(b).into()
+                }
             }
         }
     };
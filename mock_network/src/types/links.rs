@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+
+use derive_builder::Builder;
+use holochain_types::prelude::{AppEntryType, EntryVisibility, SerializedBytes, UnsafeBytes};
+
+use super::*;
+
+/// Entries and the links between them shaped like a real app's links.
+///
+/// The data starts with a create for every entry that is linked from or to
+/// so it can be added to one chain or split across many.
+pub struct LinkGraph {
+    pub data: Vec<ChainData>,
+    /// Every base and how many links it has, busiest first.
+    pub bases: Vec<(EntryHash, usize)>,
+}
+
+/// Paths like `root.3.7.1` with each level linked to the next
+/// the way paths and anchors are, and the leaves linked to targets.
+#[derive(Builder, Clone)]
+pub struct PathTreeSettings {
+    /// Levels below the root.
+    #[builder(default = "3")]
+    pub depth: usize,
+    /// Children of each path.
+    #[builder(default = "10")]
+    pub branching: usize,
+    #[builder(default = "10")]
+    pub links_per_leaf: usize,
+    #[builder(default)]
+    pub graph: GraphSettingsBuilder,
+}
+
+/// Bases with fan-out that falls away from the busiest base
+/// as a power of its rank.
+#[derive(Builder, Clone)]
+pub struct PowerLawSettings {
+    #[builder(default = "1000")]
+    pub bases: usize,
+    /// Links on the busiest base.
+    #[builder(default = "10_000")]
+    pub max_links: usize,
+    /// The base at rank `r` has `max_links / r^exponent` links.
+    #[builder(default = "1.0")]
+    pub exponent: f64,
+    #[builder(default = "1")]
+    pub min_links: usize,
+    #[builder(default)]
+    pub graph: GraphSettingsBuilder,
+}
+
+/// A few very hot bases among many quiet ones.
+#[derive(Builder, Clone)]
+pub struct HotBasesSettings {
+    #[builder(default = "3")]
+    pub hot_bases: usize,
+    #[builder(default = "20_000")]
+    pub links_per_hot_base: usize,
+    #[builder(default = "1000")]
+    pub cold_bases: usize,
+    #[builder(default = "5")]
+    pub links_per_cold_base: usize,
+    #[builder(default)]
+    pub graph: GraphSettingsBuilder,
+}
+
+/// Settings for every topology.
+#[derive(Builder, Clone, Debug)]
+pub struct GraphSettings {
+    /// How many entries links point at.
+    /// Targets are shared between bases.
+    #[builder(default = "1000")]
+    pub targets: usize,
+    #[builder(default = "0.into()")]
+    pub zome_id: ZomeId,
+    /// Bytes of random tag on each link.
+    #[builder(default = "8")]
+    pub tag_len: usize,
+    #[builder(default)]
+    pub seed: Option<u64>,
+}
+
+impl LinkGraph {
+    pub fn path_tree(settings: PathTreeSettingsBuilder) -> Self {
+        let PathTreeSettings {
+            depth,
+            branching,
+            links_per_leaf,
+            graph,
+        } = settings.build().unwrap();
+        let mut graph = Graph::new(graph.build().unwrap());
+        let root = graph.entry(b"root".to_vec());
+        let mut level = vec![("root".to_string(), root)];
+        for _ in 0..depth {
+            let mut next = Vec::with_capacity(level.len() * branching);
+            for (path, base) in &level {
+                for i in 0..branching {
+                    let component = i.to_string();
+                    let child_path = format!("{}.{}", path, component);
+                    let child = graph.entry(child_path.clone().into_bytes());
+                    graph.link(base, &child, component.into_bytes());
+                    next.push((child_path, child));
+                }
+            }
+            level = next;
+        }
+        for (_, leaf) in &level {
+            graph.link_targets(leaf, links_per_leaf);
+        }
+        graph.finish()
+    }
+
+    pub fn power_law(settings: PowerLawSettingsBuilder) -> Self {
+        let PowerLawSettings {
+            bases,
+            max_links,
+            exponent,
+            min_links,
+            graph,
+        } = settings.build().unwrap();
+        let mut graph = Graph::new(graph.build().unwrap());
+        for rank in 1..=bases {
+            let base = graph.entry(format!("base {}", rank).into_bytes());
+            let links = (max_links as f64 / (rank as f64).powf(exponent)) as usize;
+            graph.link_targets(&base, links.max(min_links));
+        }
+        graph.finish()
+    }
+
+    pub fn hot_bases(settings: HotBasesSettingsBuilder) -> Self {
+        let HotBasesSettings {
+            hot_bases,
+            links_per_hot_base,
+            cold_bases,
+            links_per_cold_base,
+            graph,
+        } = settings.build().unwrap();
+        let mut graph = Graph::new(graph.build().unwrap());
+        for i in 0..hot_bases {
+            let base = graph.entry(format!("hot {}", i).into_bytes());
+            graph.link_targets(&base, links_per_hot_base);
+        }
+        for i in 0..cold_bases {
+            let base = graph.entry(format!("cold {}", i).into_bytes());
+            graph.link_targets(&base, links_per_cold_base);
+        }
+        graph.finish()
+    }
+
+    /// Deal the data out to this many chains.
+    /// Each chain gets its share of the entries before its share of the links.
+    pub fn split(self, chains: NonZeroUsize) -> Vec<Vec<ChainData>> {
+        let chains = chains.get();
+        let mut split: Vec<Vec<ChainData>> =
+            std::iter::repeat_with(Vec::new).take(chains).collect();
+        for (i, data) in self.data.into_iter().enumerate() {
+            split[i % chains].push(data);
+        }
+        split
+    }
+}
+
+struct Graph {
+    settings: GraphSettings,
+    rng: fastrand::Rng,
+    entries: Vec<ChainData>,
+    links: Vec<ChainData>,
+    targets: Vec<EntryHash>,
+    bases: Vec<(EntryHash, usize)>,
+    /// Where each base is in `bases`.
+    base_index: HashMap<EntryHash, usize>,
+}
+
+impl Graph {
+    fn new(settings: GraphSettings) -> Self {
+        let mut graph = Self {
+            rng: settings
+                .seed
+                .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            entries: Vec::new(),
+            links: Vec::new(),
+            targets: Vec::new(),
+            bases: Vec::new(),
+            base_index: HashMap::new(),
+            settings,
+        };
+        let targets = (0..graph.settings.targets)
+            .map(|i| graph.entry(format!("target {}", i).into_bytes()))
+            .collect();
+        graph.targets = targets;
+        graph
+    }
+
+    /// Create a public app entry with these bytes.
+    fn entry(&mut self, bytes: Vec<u8>) -> EntryHash {
+        let entry = Entry::App(
+            AppEntryBytes::try_from(SerializedBytes::from(UnsafeBytes::from(bytes))).unwrap(),
+        );
+        let entry_hash = EntryHash::with_data_sync(&entry);
+        let header = CreateBuilder {
+            entry_type: Some(EntryType::App(AppEntryType::new(
+                0.into(),
+                self.settings.zome_id,
+                EntryVisibility::Public,
+            ))),
+            entry_hash: Some(entry_hash.clone()),
+        };
+        self.entries.push(ChainData {
+            header: header.into(),
+            entry: Some(entry),
+            unresolved: Default::default(),
+        });
+        entry_hash
+    }
+
+    fn link(&mut self, base: &EntryHash, target: &EntryHash, tag: Vec<u8>) {
+        let bases = &mut self.bases;
+        let i = *self.base_index.entry(base.clone()).or_insert_with(|| {
+            bases.push((base.clone(), 0));
+            bases.len() - 1
+        });
+        bases[i].1 += 1;
+        self.links.push(
+            CreateLinkBuilder {
+                base_address: Some(base.clone()),
+                target_address: Some(target.clone()),
+                zome_id: Some(self.settings.zome_id),
+                tag: Some(LinkTag::new(tag)),
+            }
+            .into(),
+        );
+    }
+
+    /// Link the base to this many random targets with random tags.
+    fn link_targets(&mut self, base: &EntryHash, links: usize) {
+        if self.targets.is_empty() {
+            return;
+        }
+        for _ in 0..links {
+            let target = self.targets[self.rng.usize(..self.targets.len())].clone();
+            let tag = std::iter::repeat_with(|| self.rng.u8(..))
+                .take(self.settings.tag_len)
+                .collect();
+            self.link(base, &target, tag);
+        }
+    }
+
+    fn finish(self) -> LinkGraph {
+        let mut bases = self.bases;
+        bases.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut data = self.entries;
+        data.extend(self.links);
+        LinkGraph { data, bases }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(targets: usize) -> GraphSettingsBuilder {
+        let mut graph = GraphSettingsBuilder::default();
+        graph.targets(targets).seed(Some(1));
+        graph
+    }
+
+    fn links(graph: &LinkGraph) -> usize {
+        graph
+            .data
+            .iter()
+            .filter(|d| matches!(d.header, ChainHeader::CreateLink(_)))
+            .count()
+    }
+
+    #[test]
+    fn path_tree_links_every_level() {
+        let mut settings = PathTreeSettingsBuilder::default();
+        settings
+            .depth(2)
+            .branching(3)
+            .links_per_leaf(4)
+            .graph(graph(10));
+        let tree = LinkGraph::path_tree(settings);
+        // 3 + 9 path links and 4 links on each of the 9 leaves.
+        assert_eq!(links(&tree), 3 + 9 + 9 * 4);
+        // The root, 12 paths and the targets.
+        assert_eq!(tree.data.len() - links(&tree), 1 + 12 + 10);
+        assert_eq!(tree.bases.len(), 1 + 3 + 9);
+    }
+
+    #[test]
+    fn power_law_falls_away_from_the_busiest_base() {
+        let mut settings = PowerLawSettingsBuilder::default();
+        settings
+            .bases(4)
+            .max_links(100)
+            .exponent(1.0)
+            .min_links(30)
+            .graph(graph(10));
+        let graph = LinkGraph::power_law(settings);
+        let counts: Vec<_> = graph.bases.iter().map(|(_, n)| *n).collect();
+        assert_eq!(counts, vec![100, 50, 33, 30]);
+    }
+
+    #[test]
+    fn hot_bases_come_first() {
+        let mut settings = HotBasesSettingsBuilder::default();
+        settings
+            .hot_bases(2)
+            .links_per_hot_base(50)
+            .cold_bases(5)
+            .links_per_cold_base(2)
+            .graph(graph(10));
+        let graph = LinkGraph::hot_bases(settings);
+        let counts: Vec<_> = graph.bases.iter().map(|(_, n)| *n).collect();
+        assert_eq!(counts, vec![50, 50, 2, 2, 2, 2, 2]);
+        assert_eq!(links(&graph), 110);
+    }
+
+    #[test]
+    fn no_targets_means_no_links() {
+        let mut settings = HotBasesSettingsBuilder::default();
+        settings.graph(graph(0));
+        let graph = LinkGraph::hot_bases(settings);
+        assert_eq!(links(&graph), 0);
+        assert!(graph.bases.is_empty());
+    }
+
+    #[test]
+    fn split_deals_entries_before_links() {
+        let mut settings = HotBasesSettingsBuilder::default();
+        settings
+            .hot_bases(1)
+            .links_per_hot_base(3)
+            .cold_bases(0)
+            .graph(graph(2));
+        let graph = LinkGraph::hot_bases(settings);
+        let chains = graph.split(NonZeroUsize::new(2).unwrap());
+        assert_eq!(chains.len(), 2);
+        assert_eq!(chains[0].len() + chains[1].len(), 3 + 3);
+        for chain in &chains {
+            let first_link = chain
+                .iter()
+                .position(|d| matches!(d.header, ChainHeader::CreateLink(_)))
+                .unwrap_or(chain.len());
+            assert!(chain[first_link..]
+                .iter()
+                .all(|d| matches!(d.header, ChainHeader::CreateLink(_))));
+        }
+    }
+
+    #[test]
+    fn same_seed_makes_same_links() {
+        let make = || {
+            let mut settings = PowerLawSettingsBuilder::default();
+            settings.bases(3).max_links(10).graph(graph(5));
+            LinkGraph::power_law(settings)
+        };
+        let tags = |graph: LinkGraph| -> Vec<_> {
+            graph
+                .data
+                .into_iter()
+                .filter_map(|d| match d.header {
+                    ChainHeader::CreateLink(link) => Some((link.target_address, link.tag)),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(tags(make()), tags(make()));
+    }
+}
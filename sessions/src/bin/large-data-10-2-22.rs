@@ -129,6 +129,7 @@ async fn main() {
                     ChainData {
                         header: header.into(),
                         entry,
+                        unresolved: Default::default(),
                     }
                 })
                 .take(70);
@@ -138,14 +139,20 @@ async fn main() {
                     data,
                     dna_hash: dna_hash.clone(),
                     genesis_settings: GenesisBuilder::default(),
+                    seed: None,
+                    forks: Vec::new(),
                 }
             })
             .collect();
         // let agent_data = vec![agent_data];
 
-        let data = GenerateBatch { agent_data };
+        let data = GenerateBatch {
+            agent_data,
+            seed: None,
+            references: References::Arbitrary,
+        };
 
-        let mut data = data.make().await;
+        let mut data = data.make().await.unwrap();
 
         let bytes: usize = data
             .values()
@@ -50,12 +50,18 @@ async fn main() {
         data,
         dna_hash: dna_hash.clone(),
         genesis_settings: GenesisBuilder::default(),
+        seed: None,
+        forks: Vec::new(),
     };
     let agent_data = vec![agent_data];
 
-    let data = GenerateBatch { agent_data };
+    let data = GenerateBatch {
+        agent_data,
+        seed: None,
+        references: References::Arbitrary,
+    };
 
-    let data = data.make().await;
+    let data = data.make().await.unwrap();
 
     let agent_info = GenerateAgentInfo {
         keystore: &keystore,
@@ -69,8 +75,10 @@ async fn main() {
     debug!(generate_data_in = ?s.elapsed());
 
     let (mut channel, network_config) = mock_network::setup(agent_info.clone());
+    // Serve the generated chains to the conductor's gossip.
+    channel.add_responder(GossipResponder::new(dna_hash.clone(), &data, &agent_info));
 
-    let jh = tokio::spawn(async move {
+    let mut jh = tokio::spawn(async move {
         let mut config = ConductorConfig::default();
         config.network = Some(network_config);
 
@@ -92,9 +100,15 @@ async fn main() {
         tokio::time::sleep(std::time::Duration::from_secs(60)).await;
     });
 
-    while let Some((msg, respond)) = channel.next().await {
-        dbg!(msg);
+    // The responders answer the conductor so anything left over is dropped.
+    loop {
+        tokio::select! {
+            msg = channel.next() => if msg.is_none() { break },
+            r = &mut jh => {
+                r.unwrap();
+                break;
+            }
+        }
     }
-
-    jh.await.unwrap();
+    warn!("traffic:\n{}", channel.stats());
 }
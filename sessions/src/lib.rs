@@ -15,4 +15,5 @@ pub mod prelude {
 }
 
 pub mod build;
+pub mod matrix;
 pub mod perf;
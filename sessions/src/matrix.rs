@@ -0,0 +1,145 @@
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use holochain::tracing::warn;
+use kitsune_p2p_types::config::tuning_params_struct::KitsuneP2pTuningParams;
+use mock_network::NetworkSettingsBuilder;
+
+/// The gossip setups a session can be run under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GossipStrategy {
+    ShardedDynamicArcs,
+    ShardedFixedArcs,
+    /// The [`GossipResponder`](mock_network::GossipResponder) only speaks
+    /// sharded gossip so simulated agents don't answer this strategy.
+    SimpleBloom,
+}
+
+impl GossipStrategy {
+    pub fn all() -> [Self; 3] {
+        [
+            Self::ShardedDynamicArcs,
+            Self::ShardedFixedArcs,
+            Self::SimpleBloom,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ShardedDynamicArcs => "sharded dynamic arcs",
+            Self::ShardedFixedArcs => "sharded fixed arcs",
+            Self::SimpleBloom => "simple bloom",
+        }
+    }
+
+    /// Switch the tuning params over to this strategy.
+    pub fn apply(&self, tuning: &mut KitsuneP2pTuningParams) {
+        match self {
+            Self::ShardedDynamicArcs => {
+                tuning.gossip_strategy = "sharded-gossip".to_string();
+                tuning.gossip_dynamic_arcs = true;
+            }
+            Self::ShardedFixedArcs => {
+                tuning.gossip_strategy = "sharded-gossip".to_string();
+                tuning.gossip_dynamic_arcs = false;
+            }
+            Self::SimpleBloom => {
+                tuning.gossip_strategy = "simple-bloom".to_string();
+                tuning.gossip_dynamic_arcs = false;
+            }
+        }
+    }
+}
+
+/// Named timings recorded by a single run of a session.
+#[derive(Clone, Debug, Default)]
+pub struct Timings(Vec<(String, Duration)>);
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, name: impl Into<String>, duration: Duration) {
+        self.0.push((name.into(), duration));
+    }
+
+    /// Record the time since `start`.
+    pub fn record_since(&mut self, name: impl Into<String>, start: Instant) {
+        self.record(name, start.elapsed());
+    }
+
+    pub fn get(&self, name: &str) -> Option<Duration> {
+        self.0.iter().find(|(n, _)| n == name).map(|(_, d)| *d)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, Duration)> {
+        self.0.iter()
+    }
+}
+
+/// The timings of every run side by side.
+#[derive(Clone, Debug, Default)]
+pub struct MatrixReport {
+    pub runs: Vec<(GossipStrategy, Timings)>,
+}
+
+impl fmt::Display for MatrixReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Every timing name in the order it was first seen.
+        let mut names: Vec<&str> = Vec::new();
+        for (_, timings) in &self.runs {
+            for (name, _) in timings.iter() {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+        write!(f, "{:width$}", "", width = width)?;
+        for (strategy, _) in &self.runs {
+            write!(f, " | {:>22}", strategy.name())?;
+        }
+        writeln!(f)?;
+        for name in names {
+            write!(f, "{:width$}", name, width = width)?;
+            for (_, timings) in &self.runs {
+                match timings.get(name) {
+                    Some(d) => write!(f, " | {:>22}", format!("{:?}", d))?,
+                    None => write!(f, " | {:>22}", "-")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the same session body once for each strategy.
+///
+/// The body gets network settings with the strategy applied
+/// on top of their tuning and returns the timings it recorded.
+/// Runs happen one after another so they don't compete for resources.
+pub async fn run_matrix<F, Fut>(
+    strategies: &[GossipStrategy],
+    settings: NetworkSettingsBuilder,
+    mut body: F,
+) -> MatrixReport
+where
+    F: FnMut(GossipStrategy, NetworkSettingsBuilder) -> Fut,
+    Fut: Future<Output = Timings>,
+{
+    let mut report = MatrixReport::default();
+    for strategy in strategies {
+        let mut settings = settings.clone();
+        strategy.apply(settings.tuning_mut());
+        let s = Instant::now();
+        let mut timings = body(*strategy, settings).await;
+        timings.record("total", s.elapsed());
+        warn!(strategy = strategy.name(), ?timings);
+        report.runs.push((*strategy, timings));
+    }
+    warn!("MATRIX:\n{}", report);
+    report
+}